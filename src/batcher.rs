@@ -0,0 +1,66 @@
+use crate::traits::TheaMessage;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Longest a deposit waits for more deposits before its batch is submitted
+const BATCH_TIMEOUT: Duration = Duration::from_secs(3);
+/// Upper bound on deposits per incoming message, keeps the extrinsic well within block limits
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Groups EVM deposits from nearby blocks so they are submitted as one Thea incoming message
+#[derive(Debug)]
+pub struct DepositBatcher {
+    // Number of consecutive EVM blocks a single batch may span
+    window: u64,
+    deposits: Vec<TheaMessage>,
+    first_block: u64,
+    deadline: Option<Instant>,
+}
+
+impl DepositBatcher {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window: window.max(1),
+            deposits: vec![],
+            first_block: 0,
+            deadline: None,
+        }
+    }
+
+    /// EVM block the deposit was observed in, `None` for messages that are not batched
+    pub fn block_number(message: &TheaMessage) -> Option<u64> {
+        match message {
+            TheaMessage::EvmDeposit(deposit) => Some(deposit.block_number),
+            TheaMessage::ObEvmDeposit(deposit) => Some(deposit.block_number),
+            _ => None,
+        }
+    }
+
+    /// Adds a deposit, returning the pending batch if the deposit does not fit into it
+    pub fn push(&mut self, deposit: TheaMessage, block_number: u64) -> Option<Vec<TheaMessage>> {
+        let fits = block_number >= self.first_block
+            && block_number - self.first_block < self.window
+            && self.deposits.len() < MAX_BATCH_SIZE;
+        let batch = if !self.deposits.is_empty() && !fits {
+            Some(self.take())
+        } else {
+            None
+        };
+        if self.deposits.is_empty() {
+            self.first_block = block_number;
+            self.deadline = Some(Instant::now() + BATCH_TIMEOUT);
+        }
+        self.deposits.push(deposit);
+        batch
+    }
+
+    /// When the pending batch has to be submitted even if no further deposits arrive
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn take(&mut self) -> Vec<TheaMessage> {
+        self.deadline = None;
+        std::mem::take(&mut self.deposits)
+    }
+}
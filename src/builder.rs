@@ -4,6 +4,11 @@ use ethers::abi::{ethabi, Contract};
 use std::path::PathBuf;
 use ethers::contract::abigen;
 use crate::error::RelayerError;
+use crate::signer::SubstrateSigner;
+use crate::evmsigner::{LocalSigner, RelayerSigner};
+use crate::feestrategy::FeeStrategy;
+use crate::confirmation::DepositConfirmation;
+use std::sync::Arc;
 
 pub struct NoDestinationChain;
 pub struct DestinationChain(String);
@@ -11,50 +16,76 @@ pub struct NoContract;
 pub struct EVMContract(Contract);
 pub struct NoSeed;
 pub struct Seed(String);
+pub struct SubstrateSeed(SubstrateSigner);
+pub struct EvmSigner(Arc<dyn RelayerSigner>);
 pub struct NoTheaContractAddress;
 pub struct TheaContractAddress(String);
 
-pub struct NoSubstrateNetworkId;
-pub struct SubstrateNetworkId(u8);
+pub struct NoNetworkId;
+pub struct TheaNetworkId(u8);
 
-pub struct Builder<Url, ContractFile, SeedString, ContractAddress> {
+pub struct Builder<Url, ContractFile, SeedString, ContractAddress, NetworkId> {
     chain_url: Url,
     contract: ContractFile,
     seed: SeedString,
-    contract_address: ContractAddress
+    contract_address: ContractAddress,
+    network_id: NetworkId,
+    chain_id: Option<u64>,
+    start_block: Option<u64>,
+    log_page_size: u64,
+    tx_confirmations: u64,
+    fee_strategy: FeeStrategy,
+    deposit_confirmation: DepositConfirmation
 }
 
-impl Default for Builder<NoDestinationChain, NoContract, NoSeed, NoTheaContractAddress> {
-    fn default() -> Builder<NoDestinationChain, NoContract, NoSeed, NoTheaContractAddress> {
+const DEFAULT_LOG_PAGE_SIZE: u64 = 1000;
+const DEFAULT_TX_CONFIRMATIONS: u64 = 1;
+
+impl Default for Builder<NoDestinationChain, NoContract, NoSeed, NoTheaContractAddress, NoNetworkId> {
+    fn default() -> Builder<NoDestinationChain, NoContract, NoSeed, NoTheaContractAddress, NoNetworkId> {
         Builder {
             chain_url: NoDestinationChain,
             contract: NoContract,
             seed: NoSeed,
-            contract_address: NoTheaContractAddress
+            contract_address: NoTheaContractAddress,
+            network_id: NoNetworkId,
+            chain_id: None,
+            start_block: None,
+            log_page_size: DEFAULT_LOG_PAGE_SIZE,
+            tx_confirmations: DEFAULT_TX_CONFIRMATIONS,
+            fee_strategy: FeeStrategy::default(),
+            deposit_confirmation: DepositConfirmation::default()
         }
     }
 }
 
-impl<Url, ContractFile, SeedString, ContractAddress>
-    Builder<Url, ContractFile, SeedString, ContractAddress>
+impl<Url, ContractFile, SeedString, ContractAddress, NetworkId>
+    Builder<Url, ContractFile, SeedString, ContractAddress, NetworkId>
 {
     pub fn chain_url(
         self,
         chain_url: String,
-    ) -> Builder<DestinationChain, ContractFile, SeedString, ContractAddress>
+    ) -> Builder<DestinationChain, ContractFile, SeedString, ContractAddress, NetworkId>
     {
         Builder {
             chain_url: DestinationChain(chain_url),
             contract: self.contract,
             seed: self.seed,
             contract_address: self.contract_address,
+            network_id: self.network_id,
+            chain_id: self.chain_id,
+            start_block: self.start_block,
+            log_page_size: self.log_page_size,
+            tx_confirmations: self.tx_confirmations,
+            fee_strategy: self.fee_strategy,
+            deposit_confirmation: self.deposit_confirmation,
         }
     }
 
     pub fn contract(
         self,
         contract_location: PathBuf,
-    ) -> Result<Builder<Url, EVMContract, SeedString, ContractAddress>, RelayerError> {
+    ) -> Result<Builder<Url, EVMContract, SeedString, ContractAddress, NetworkId>, RelayerError> {
         let log_abi_file = std::fs::File::open(contract_location)?;
         let log_contract = ethabi::Contract::load(log_abi_file)?;
 
@@ -62,50 +93,179 @@ impl<Url, ContractFile, SeedString, ContractAddress>
             chain_url: self.chain_url,
             contract: EVMContract(log_contract),
             seed: self.seed,
-            contract_address: self.contract_address
+            contract_address: self.contract_address,
+            network_id: self.network_id,
+            chain_id: self.chain_id,
+            start_block: self.start_block,
+            log_page_size: self.log_page_size,
+            tx_confirmations: self.tx_confirmations,
+            fee_strategy: self.fee_strategy,
+            deposit_confirmation: self.deposit_confirmation,
         })
     }
 
     pub fn seed(
         self,
         seed: String,
-    ) -> Builder<Url, ContractFile, Seed, ContractAddress> {
+    ) -> Builder<Url, ContractFile, Seed, ContractAddress, NetworkId> {
         Builder {
             chain_url: self.chain_url,
             contract: self.contract,
             seed: Seed(seed),
-            contract_address: self.contract_address
+            contract_address: self.contract_address,
+            network_id: self.network_id,
+            chain_id: self.chain_id,
+            start_block: self.start_block,
+            log_page_size: self.log_page_size,
+            tx_confirmations: self.tx_confirmations,
+            fee_strategy: self.fee_strategy,
+            deposit_confirmation: self.deposit_confirmation,
+        }
+    }
+
+    /// Signs EVM transactions with the given signer instead of a local seed
+    pub fn evm_signer(
+        self,
+        signer: Arc<dyn RelayerSigner>,
+    ) -> Builder<Url, ContractFile, EvmSigner, ContractAddress, NetworkId> {
+        Builder {
+            chain_url: self.chain_url,
+            contract: self.contract,
+            seed: EvmSigner(signer),
+            contract_address: self.contract_address,
+            network_id: self.network_id,
+            chain_id: self.chain_id,
+            start_block: self.start_block,
+            log_page_size: self.log_page_size,
+            tx_confirmations: self.tx_confirmations,
+            fee_strategy: self.fee_strategy,
+            deposit_confirmation: self.deposit_confirmation,
+        }
+    }
+
+    pub fn substrate_signer(
+        self,
+        signer: SubstrateSigner,
+    ) -> Builder<Url, ContractFile, SubstrateSeed, ContractAddress, NetworkId> {
+        Builder {
+            chain_url: self.chain_url,
+            contract: self.contract,
+            seed: SubstrateSeed(signer),
+            contract_address: self.contract_address,
+            network_id: self.network_id,
+            chain_id: self.chain_id,
+            start_block: self.start_block,
+            log_page_size: self.log_page_size,
+            tx_confirmations: self.tx_confirmations,
+            fee_strategy: self.fee_strategy,
+            deposit_confirmation: self.deposit_confirmation,
         }
     }
 
     pub fn contract_address(
         self,
         contract_address: String,
-    ) -> Builder<Url, ContractFile, SeedString, TheaContractAddress> {
+    ) -> Builder<Url, ContractFile, SeedString, TheaContractAddress, NetworkId> {
+        Builder {
+            chain_url: self.chain_url,
+            contract: self.contract,
+            seed: self.seed,
+            contract_address: TheaContractAddress(contract_address),
+            network_id: self.network_id,
+            chain_id: self.chain_id,
+            start_block: self.start_block,
+            log_page_size: self.log_page_size,
+            tx_confirmations: self.tx_confirmations,
+            fee_strategy: self.fee_strategy,
+            deposit_confirmation: self.deposit_confirmation,
+        }
+    }
+
+    /// Thea network id the client relays for
+    pub fn network_id(
+        self,
+        network_id: u8,
+    ) -> Builder<Url, ContractFile, SeedString, ContractAddress, TheaNetworkId> {
         Builder {
             chain_url: self.chain_url,
             contract: self.contract,
             seed: self.seed,
-            contract_address: TheaContractAddress(contract_address)
+            contract_address: self.contract_address,
+            network_id: TheaNetworkId(network_id),
+            chain_id: self.chain_id,
+            start_block: self.start_block,
+            log_page_size: self.log_page_size,
+            tx_confirmations: self.tx_confirmations,
+            fee_strategy: self.fee_strategy,
+            deposit_confirmation: self.deposit_confirmation,
         }
     }
+
+    /// Expected EVM chain id, detected from the node when not set
+    pub fn chain_id(mut self, chain_id: Option<u64>) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Block to backfill EVM logs from when no checkpoint exists yet
+    pub fn start_block(mut self, start_block: Option<u64>) -> Self {
+        self.start_block = start_block;
+        self
+    }
+
+    /// Maximum number of blocks queried per `eth_getLogs` call during backfill
+    pub fn log_page_size(mut self, log_page_size: u64) -> Self {
+        self.log_page_size = log_page_size;
+        self
+    }
+
+    /// Blocks a transaction receipt has to be buried under before it counts as final
+    pub fn tx_confirmations(mut self, tx_confirmations: u64) -> Self {
+        self.tx_confirmations = tx_confirmations;
+        self
+    }
+
+    /// Pricing of EVM transactions, EIP-1559 without caps by default
+    pub fn fee_strategy(mut self, fee_strategy: FeeStrategy) -> Self {
+        self.fee_strategy = fee_strategy;
+        self
+    }
+
+    /// How deep deposit logs have to be before they are relayed, 12 blocks by default
+    pub fn deposit_confirmation(mut self, deposit_confirmation: DepositConfirmation) -> Self {
+        self.deposit_confirmation = deposit_confirmation;
+        self
+    }
+}
+
+impl Builder<DestinationChain, EVMContract, Seed, TheaContractAddress, TheaNetworkId> {
+    pub async fn build(self) -> Result<EvmClient, RelayerError> {
+        let signer = LocalSigner::from_seed(&self.seed.0)?;
+        self.evm_signer(Arc::new(signer)).build().await
+    }
 }
 
-impl Builder<DestinationChain, EVMContract, Seed, TheaContractAddress> {
+impl Builder<DestinationChain, EVMContract, EvmSigner, TheaContractAddress, TheaNetworkId> {
     pub async fn build(self) -> Result<EvmClient, RelayerError> {
         EvmClient::new(
             self.chain_url.0,
             self.contract.0,
             self.seed.0,
-            self.contract_address.0
+            self.contract_address.0,
+            self.network_id.0,
+            self.chain_id,
+            self.start_block,
+            self.log_page_size,
+            self.tx_confirmations,
+            self.fee_strategy,
+            self.deposit_confirmation
         )
         .await
     }
 }
 
-impl Builder<DestinationChain, NoContract, NoSeed, NoTheaContractAddress> {
-    //FIXME: Take seed while building
+impl Builder<DestinationChain, NoContract, SubstrateSeed, NoTheaContractAddress, TheaNetworkId> {
     pub async fn build(self) -> Result<SubstrateClient, RelayerError> {
-        SubstrateClient::initialize(self.chain_url.0).await
+        SubstrateClient::initialize(self.chain_url.0, self.seed.0, self.network_id.0).await
     }
 }
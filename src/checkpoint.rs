@@ -0,0 +1,391 @@
+use crate::error::RelayerError;
+use crate::traits::TheaMessage;
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use sp_core::hashing::sha2_256;
+use std::path::PathBuf;
+
+const OUTGOING_NONCE_TREE: &str = "substrate_outgoing_nonce";
+const FINALIZED_BLOCK_TREE: &str = "substrate_finalized_block";
+const EVM_CURSOR_TREE: &str = "evm_cursor";
+const IN_FLIGHT_TREE: &str = "in_flight";
+const DEAD_LETTER_TREE: &str = "dead_letter";
+const PENDING_INCOMING_TREE: &str = "pending_incoming";
+const EXTRINSIC_OUTCOME_TREE: &str = "extrinsic_outcome";
+const EVM_TX_OUTCOME_TREE: &str = "evm_tx_outcome";
+const PENDING_CLAIM_TREE: &str = "pending_claim";
+const CLAIMED_WITHDRAWAL_TREE: &str = "claimed_withdrawal";
+const WATCHTOWER_ALERT_TREE: &str = "watchtower_alert";
+
+/// Position of the last processed log on the EVM side
+#[derive(Clone, Copy, Encode, Decode, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub struct EvmCursor {
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl EvmCursor {
+    pub fn new(block_number: u64, log_index: u64) -> Self {
+        Self {
+            block_number,
+            log_index,
+        }
+    }
+}
+
+/// EVM event streams that keep their own cursor
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EvmStream {
+    Deposit,
+    ObDeposit,
+    /// `MessageProcessed` and `WithdrawalClaimed` logs scanned by the claimer
+    Claims,
+    /// `MessageProcessed` logs checked by the watchtower
+    Watchtower,
+}
+
+impl EvmStream {
+    fn key(&self) -> &'static [u8] {
+        match self {
+            EvmStream::Deposit => b"deposit",
+            EvmStream::ObDeposit => b"ob_deposit",
+            EvmStream::Claims => b"claims",
+            EvmStream::Watchtower => b"watchtower",
+        }
+    }
+}
+
+/// Message that could not be relayed, kept for manual inspection
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub message: TheaMessage,
+    pub error: String,
+    pub attempts: u32,
+}
+
+/// Incoming message submitted to Polkadex whose nonce is not finalized yet
+#[derive(Clone, Encode, Decode, Debug, Eq, PartialEq)]
+pub struct PendingIncoming {
+    pub nonce: u64,
+    pub block_no: u64,
+    /// SCALE encoded deposits
+    pub data: Vec<u8>,
+    /// Finalized block number the extrinsic was made mortal from, `None` while no extrinsic carries the message
+    pub submitted_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ExtrinsicStatus {
+    /// Block hash the extrinsic was finalized in
+    Finalized([u8; 32]),
+    /// Included but the dispatch failed
+    Failed(String),
+    /// Never finalized, left for reconciliation
+    Dropped(String),
+}
+
+/// How an incoming message extrinsic submitted to Polkadex ended
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExtrinsicOutcome {
+    pub network_id: u8,
+    pub nonce: u64,
+    pub extrinsic_hash: [u8; 32],
+    pub status: ExtrinsicStatus,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum EvmTxStatus {
+    /// Block number the transaction was mined in
+    Confirmed(u64),
+    /// Decoded revert reason
+    Reverted(String),
+    /// Not mined after the last fee bump
+    Stuck,
+}
+
+/// How a transaction sent to the Thea contract ended, along with every replacement broadcast for it
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EvmTxOutcome {
+    pub nonce: u64,
+    pub tx_hashes: Vec<[u8; 32]>,
+    pub status: EvmTxStatus,
+}
+
+impl EvmTxOutcome {
+    pub fn new(nonce: u64, tx_hashes: &[ethers::types::H256], status: EvmTxStatus) -> Self {
+        Self {
+            nonce,
+            tx_hashes: tx_hashes.iter().map(|tx_hash| tx_hash.0).collect(),
+            status,
+        }
+    }
+}
+
+/// Message processed by the contract whose withdrawals are not all claimed yet
+#[derive(Clone, Encode, Decode, Debug, Eq, PartialEq)]
+pub struct PendingClaim {
+    pub nonce: u64,
+    /// Timestamp of the block the message was processed in
+    pub processed_at: u64,
+    /// Withdrawals signed on Polkadex, the contract stores them at the indexes below it
+    pub withdrawals: u64,
+}
+
+/// Pending withdrawal on the EVM side that does not match what Polkadex signed
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WatchtowerAlert {
+    pub nonce: u64,
+    pub index: u64,
+    pub reason: String,
+    /// `blockTransaction` sent for it, `None` if it could not be sent
+    pub block_tx: Option<EvmTxOutcome>,
+    /// Why `blockTransaction` could not be sent
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CheckpointStore {
+    db: sled::Db,
+}
+
+impl CheckpointStore {
+    pub fn new(db: sled::Db) -> Self {
+        Self { db }
+    }
+
+    pub fn open(path: PathBuf) -> Result<Self, RelayerError> {
+        Ok(Self::new(sled::open(path)?))
+    }
+
+    /// Last Substrate outgoing nonce handed over to the relayer for the given network
+    pub fn last_outgoing_nonce(&self, network_id: u8) -> Result<Option<u64>, RelayerError> {
+        let tree = self.db.open_tree(OUTGOING_NONCE_TREE)?;
+        match tree.get([network_id])? {
+            Some(value) => Ok(Some(Decode::decode(&mut &value[..])?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_last_outgoing_nonce(&self, network_id: u8, nonce: u64) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(OUTGOING_NONCE_TREE)?;
+        tree.insert([network_id], nonce.encode())?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    /// Last finalized Substrate block whose signed outgoing messages were walked for the given network
+    pub fn last_finalized_block(&self, network_id: u8) -> Result<Option<[u8; 32]>, RelayerError> {
+        let tree = self.db.open_tree(FINALIZED_BLOCK_TREE)?;
+        match tree.get([network_id])? {
+            Some(value) => Ok(Some(Decode::decode(&mut &value[..])?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_last_finalized_block(&self, network_id: u8, block_hash: [u8; 32]) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(FINALIZED_BLOCK_TREE)?;
+        tree.insert([network_id], block_hash.encode())?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn evm_cursor(&self, stream: EvmStream) -> Result<Option<EvmCursor>, RelayerError> {
+        let tree = self.db.open_tree(EVM_CURSOR_TREE)?;
+        match tree.get(stream.key())? {
+            Some(value) => Ok(Some(Decode::decode(&mut &value[..])?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_evm_cursor(&self, stream: EvmStream, cursor: EvmCursor) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(EVM_CURSOR_TREE)?;
+        tree.insert(stream.key(), cursor.encode())?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn in_flight_key(message: &TheaMessage) -> Result<[u8; 32], RelayerError> {
+        Ok(sha2_256(&serde_json::to_vec(message)?))
+    }
+
+    /// Records a message that was picked up from a source chain but not yet submitted
+    pub fn add_in_flight(&self, message: &TheaMessage) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(IN_FLIGHT_TREE)?;
+        let message = serde_json::to_vec(message)?;
+        // Prefix with a sequence number so messages are replayed in the order they were seen
+        let mut value = self.db.generate_id()?.to_be_bytes().to_vec();
+        value.extend_from_slice(&message);
+        tree.insert(sha2_256(&message), value)?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn remove_in_flight(&self, key: [u8; 32]) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(IN_FLIGHT_TREE)?;
+        tree.remove(key)?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn in_flight(&self) -> Result<Vec<TheaMessage>, RelayerError> {
+        let tree = self.db.open_tree(IN_FLIGHT_TREE)?;
+        let mut messages: Vec<(Vec<u8>, TheaMessage)> = vec![];
+        for entry in tree.iter() {
+            let (_, value) = entry?;
+            let (sequence, message) = value.split_at(8);
+            messages.push((sequence.to_vec(), serde_json::from_slice(message)?));
+        }
+        messages.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(messages.into_iter().map(|(_, message)| message).collect())
+    }
+
+    pub fn add_dead_letter(&self, message: &TheaMessage, error: &RelayerError, attempts: u32) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(DEAD_LETTER_TREE)?;
+        let dead_letter = DeadLetter {
+            message: message.clone(),
+            error: error.to_string(),
+            attempts,
+        };
+        tree.insert(Self::in_flight_key(message)?, serde_json::to_vec(&dead_letter)?)?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetter>, RelayerError> {
+        let tree = self.db.open_tree(DEAD_LETTER_TREE)?;
+        let mut dead_letters = vec![];
+        for entry in tree.iter() {
+            let (_, value) = entry?;
+            dead_letters.push(serde_json::from_slice(&value)?);
+        }
+        Ok(dead_letters)
+    }
+
+    pub fn add_pending_incoming(&self, network_id: u8, pending: &PendingIncoming) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(PENDING_INCOMING_TREE)?;
+        tree.insert(Self::pending_incoming_key(network_id, pending.nonce), pending.encode())?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn remove_pending_incoming(&self, network_id: u8, nonce: u64) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(PENDING_INCOMING_TREE)?;
+        tree.remove(Self::pending_incoming_key(network_id, nonce))?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    /// Pending incoming messages of the network, ordered by nonce
+    pub fn pending_incoming(&self, network_id: u8) -> Result<Vec<PendingIncoming>, RelayerError> {
+        let tree = self.db.open_tree(PENDING_INCOMING_TREE)?;
+        let mut pending = vec![];
+        for entry in tree.scan_prefix([network_id]) {
+            let (_, value) = entry?;
+            pending.push(Decode::decode(&mut &value[..])?);
+        }
+        Ok(pending)
+    }
+
+    pub fn add_extrinsic_outcome(&self, outcome: &ExtrinsicOutcome) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(EXTRINSIC_OUTCOME_TREE)?;
+        tree.insert(outcome.extrinsic_hash, serde_json::to_vec(outcome)?)?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn extrinsic_outcomes(&self) -> Result<Vec<ExtrinsicOutcome>, RelayerError> {
+        let tree = self.db.open_tree(EXTRINSIC_OUTCOME_TREE)?;
+        let mut outcomes = vec![];
+        for entry in tree.iter() {
+            let (_, value) = entry?;
+            outcomes.push(serde_json::from_slice(&value)?);
+        }
+        Ok(outcomes)
+    }
+
+    pub fn add_evm_tx_outcome(&self, outcome: &EvmTxOutcome) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(EVM_TX_OUTCOME_TREE)?;
+        tree.insert(outcome.nonce.to_be_bytes(), serde_json::to_vec(outcome)?)?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn evm_tx_outcomes(&self) -> Result<Vec<EvmTxOutcome>, RelayerError> {
+        let tree = self.db.open_tree(EVM_TX_OUTCOME_TREE)?;
+        let mut outcomes = vec![];
+        for entry in tree.iter() {
+            let (_, value) = entry?;
+            outcomes.push(serde_json::from_slice(&value)?);
+        }
+        Ok(outcomes)
+    }
+
+    pub fn add_pending_claim(&self, claim: &PendingClaim) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(PENDING_CLAIM_TREE)?;
+        tree.insert(claim.nonce.to_be_bytes(), claim.encode())?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn remove_pending_claim(&self, nonce: u64) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(PENDING_CLAIM_TREE)?;
+        tree.remove(nonce.to_be_bytes())?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    /// Messages with unclaimed withdrawals, ordered by nonce
+    pub fn pending_claims(&self) -> Result<Vec<PendingClaim>, RelayerError> {
+        let tree = self.db.open_tree(PENDING_CLAIM_TREE)?;
+        let mut claims = vec![];
+        for entry in tree.iter() {
+            let (_, value) = entry?;
+            claims.push(Decode::decode(&mut &value[..])?);
+        }
+        Ok(claims)
+    }
+
+    /// Records a withdrawal claimed by the relayer or anyone else
+    pub fn add_claimed_withdrawal(&self, nonce: u64, index: u64) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(CLAIMED_WITHDRAWAL_TREE)?;
+        tree.insert(Self::withdrawal_key(nonce, index), vec![])?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn is_withdrawal_claimed(&self, nonce: u64, index: u64) -> Result<bool, RelayerError> {
+        let tree = self.db.open_tree(CLAIMED_WITHDRAWAL_TREE)?;
+        Ok(tree.contains_key(Self::withdrawal_key(nonce, index))?)
+    }
+
+    pub fn add_watchtower_alert(&self, alert: &WatchtowerAlert) -> Result<(), RelayerError> {
+        let tree = self.db.open_tree(WATCHTOWER_ALERT_TREE)?;
+        tree.insert(Self::withdrawal_key(alert.nonce, alert.index), serde_json::to_vec(alert)?)?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    pub fn watchtower_alerts(&self) -> Result<Vec<WatchtowerAlert>, RelayerError> {
+        let tree = self.db.open_tree(WATCHTOWER_ALERT_TREE)?;
+        let mut alerts = vec![];
+        for entry in tree.iter() {
+            let (_, value) = entry?;
+            alerts.push(serde_json::from_slice(&value)?);
+        }
+        Ok(alerts)
+    }
+
+    fn withdrawal_key(nonce: u64, index: u64) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[..8].copy_from_slice(&nonce.to_be_bytes());
+        key[8..].copy_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    // Big endian nonce so entries iterate in nonce order
+    fn pending_incoming_key(network_id: u8, nonce: u64) -> Vec<u8> {
+        let mut key = vec![network_id];
+        key.extend_from_slice(&nonce.to_be_bytes());
+        key
+    }
+}
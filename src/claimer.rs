@@ -0,0 +1,158 @@
+use crate::checkpoint::{CheckpointStore, EvmCursor, EvmStream, EvmTxStatus, PendingClaim};
+use crate::error::RelayerError;
+use crate::evmclient::EvmClient;
+use crate::substrateclient::SubstrateClient;
+use ethers::types::H256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often new processed messages are picked up and due withdrawals claimed
+const CLAIM_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Claims withdrawals of processed messages on behalf of their recipients once the contract's delay has passed
+#[derive(Clone, Debug)]
+pub struct WithdrawalClaimer {
+    evm_client: EvmClient,
+    substrate_client: SubstrateClient,
+    checkpoint: CheckpointStore,
+    claim_delay: Duration,
+}
+
+impl WithdrawalClaimer {
+    pub fn new(
+        evm_client: EvmClient,
+        substrate_client: SubstrateClient,
+        checkpoint: CheckpointStore,
+        claim_delay: Duration,
+    ) -> Self {
+        Self {
+            evm_client,
+            substrate_client,
+            checkpoint,
+            claim_delay,
+        }
+    }
+
+    pub async fn run(&self) -> Result<(), RelayerError> {
+        println!("Claiming withdrawals {:?} after their message is processed", self.claim_delay);
+        let mut interval = tokio::time::interval(CLAIM_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.scan().await?;
+            self.claim_due().await?;
+        }
+    }
+
+    /// Records messages processed and withdrawals claimed since the last scanned block
+    async fn scan(&self) -> Result<(), RelayerError> {
+        let head = self.evm_client.block_number().await?;
+        let from_block = match self.checkpoint.evm_cursor(EvmStream::Claims)? {
+            Some(cursor) => cursor.block_number.saturating_add(1),
+            None => self.evm_client.start_block().unwrap_or(head),
+        };
+        if from_block > head {
+            return Ok(());
+        }
+        for (nonce, block_number) in self.evm_client.processed_messages(from_block..=head).await? {
+            let processed_at = self.evm_client.block_timestamp(block_number).await?;
+            let withdrawals = self.signed_withdrawals(nonce).await?;
+            self.checkpoint.add_pending_claim(&PendingClaim {
+                nonce,
+                processed_at,
+                withdrawals,
+            })?;
+        }
+        for (nonce, index) in self.evm_client.claimed_withdrawals(from_block..=head).await? {
+            self.checkpoint.add_claimed_withdrawal(nonce, index)?;
+        }
+        self.checkpoint.set_evm_cursor(EvmStream::Claims, EvmCursor::new(head, 0))
+    }
+
+    /// Number of withdrawals Polkadex signed for the message, read at the finalized block the relayer walked
+    async fn signed_withdrawals(&self, nonce: u64) -> Result<u64, RelayerError> {
+        let block_hash = self
+            .checkpoint
+            .last_finalized_block(self.substrate_client.network_id())?
+            .ok_or(RelayerError::OutgoingMessageNotFound(nonce))?;
+        let withdrawals = self.substrate_client.outgoing_withdrawals(nonce, H256(block_hash)).await?;
+        Ok(withdrawals.len() as u64)
+    }
+
+    /// Claims every unblocked and unclaimed withdrawal of the messages past the delay
+    async fn claim_due(&self) -> Result<(), RelayerError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let claims = self.checkpoint.pending_claims()?;
+        let due = due_withdrawals(&claims, now, self.claim_delay, |nonce, index| {
+            self.checkpoint.is_withdrawal_claimed(nonce, index)
+        })?;
+        for (nonce, indexes) in due {
+            let mut settled = true;
+            for index in indexes {
+                let withdrawal = match self.evm_client.pending_withdrawal(nonce, index).await {
+                    Ok(Some(withdrawal)) => withdrawal,
+                    Ok(None) => continue,
+                    Err(err) if err.is_fatal() => return Err(err),
+                    Err(err) => {
+                        println!("Failed to read withdrawal {:?} of message {:?}: {:?}", index, nonce, err);
+                        settled = false;
+                        continue;
+                    }
+                };
+                if withdrawal.is_blocked {
+                    continue;
+                }
+                match self.evm_client.claim_withdrawal(nonce, withdrawal.index).await {
+                    Ok(outcome) => {
+                        self.checkpoint.add_evm_tx_outcome(&outcome)?;
+                        if let EvmTxStatus::Confirmed(_) = outcome.status {
+                            self.checkpoint.add_claimed_withdrawal(nonce, withdrawal.index)?;
+                        } else {
+                            settled = false;
+                        }
+                    }
+                    Err(err) if err.is_fatal() => return Err(err),
+                    // Retried on the next pass, unless the scan sees someone else claimed it
+                    Err(err) => {
+                        println!("Failed to claim withdrawal {:?} of message {:?}: {:?}", withdrawal.index, nonce, err);
+                        settled = false;
+                    }
+                }
+            }
+            if settled {
+                self.checkpoint.remove_pending_claim(nonce)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Message nonces past the claim delay along with the withdrawal indexes still to claim. Claimed ones are
+/// known from the scanned events and not read again.
+pub fn due_withdrawals<F>(
+    claims: &[PendingClaim],
+    now: u64,
+    claim_delay: Duration,
+    is_claimed: F,
+) -> Result<Vec<(u64, Vec<u64>)>, RelayerError>
+where
+    F: Fn(u64, u64) -> Result<bool, RelayerError>,
+{
+    let mut due = vec![];
+    for claim in claims.iter().filter(|claim| claim.is_due(now, claim_delay)) {
+        let mut indexes = vec![];
+        for index in 0..claim.withdrawals {
+            if !is_claimed(claim.nonce, index)? {
+                indexes.push(index);
+            }
+        }
+        due.push((claim.nonce, indexes));
+    }
+    Ok(due)
+}
+
+impl PendingClaim {
+    pub fn is_due(&self, now: u64, claim_delay: Duration) -> bool {
+        now >= self.processed_at.saturating_add(claim_delay.as_secs())
+    }
+}
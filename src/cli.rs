@@ -1,4 +1,6 @@
 use std::path::PathBuf;
+use crate::signer::KeyType;
+use ethers::types::Address;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -29,16 +31,62 @@ pub struct Cli {
     long = "evm-network-id"
     )]
     pub evn_network_id: u8,
+    #[structopt(short = "s", long = "evm-seed")]
+    pub evm_seed: Option<String>,
+    #[structopt(long = "evm-keystore", parse(from_os_str))]
+    pub evm_keystore: Option<PathBuf>,
+    #[structopt(long = "evm-keystore-password-file", parse(from_os_str))]
+    pub evm_keystore_password_file: Option<PathBuf>,
+    #[structopt(long = "evm-remote-signer-url")]
+    pub evm_remote_signer_url: Option<String>,
+    #[structopt(long = "evm-remote-signer-address")]
+    pub evm_remote_signer_address: Option<Address>,
+    #[structopt(short = "q", long = "sub-phase")]
+    pub sub_phase: Option<String>,
+    #[structopt(long = "sub-key-file", parse(from_os_str))]
+    pub sub_key_file: Option<PathBuf>,
+    #[structopt(long = "sub-key-type", default_value = "sr25519")]
+    pub sub_key_type: KeyType,
+    #[structopt(long = "sub-keystore", parse(from_os_str))]
+    pub sub_keystore: Option<PathBuf>,
+    #[structopt(long = "sub-keystore-password-file", parse(from_os_str))]
+    pub sub_keystore_password_file: Option<PathBuf>,
     #[structopt(
-        short = "s",
-        long = "evm-seed",
-        default_value = "c05c6ae125754dd17f36bcc5318498ce5c6c2f0e9e1116c68b77889a8be2ff02"
+    short = "d",
+    long = "db-path",
+    parse(from_os_str),
+    default_value = "relayer_db"
     )]
-    pub evm_seed: String,
-    #[structopt(
-    short = "q",
-    long = "sub-phase",
-    default_value = "c05c6ae125754dd17f36bcc5318498ce5c6c2f0e9e1116c68b77889a8be2ff02"
-    )]
-    pub sub_phase: String,
+    pub db_path: PathBuf,
+    #[structopt(long = "evm-chain-id")]
+    pub evm_chain_id: Option<u64>,
+    #[structopt(long = "evm-start-block")]
+    pub evm_start_block: Option<u64>,
+    #[structopt(long = "evm-log-page-size", default_value = "1000")]
+    pub evm_log_page_size: u64,
+    #[structopt(long = "evm-tx-confirmations", default_value = "1")]
+    pub evm_tx_confirmations: u64,
+    #[structopt(long = "evm-deposit-confirmations", default_value = "12")]
+    pub evm_deposit_confirmations: u64,
+    #[structopt(long = "evm-deposit-finalized")]
+    pub evm_deposit_finalized: bool,
+    #[structopt(long = "evm-legacy-tx")]
+    pub evm_legacy_tx: bool,
+    #[structopt(long = "evm-max-fee-gwei")]
+    pub evm_max_fee_gwei: Option<u64>,
+    #[structopt(long = "evm-max-priority-fee-gwei")]
+    pub evm_max_priority_fee_gwei: Option<u64>,
+    #[structopt(long = "evm-gas-multiplier-percent", default_value = "120")]
+    pub evm_gas_multiplier_percent: u64,
+    #[structopt(long = "evm-gas-price-ceiling-gwei")]
+    pub evm_gas_price_ceiling_gwei: Option<u64>,
+    #[structopt(long = "deposit-batch-blocks", default_value = "1")]
+    pub deposit_batch_blocks: u64,
+    #[structopt(long = "claim-withdrawals", requires = "withdrawal_claim_delay_secs")]
+    pub claim_withdrawals: bool,
+    /// Delay the contract enforces before a withdrawal can be claimed, the contract does not expose it
+    #[structopt(long = "withdrawal-claim-delay-secs")]
+    pub withdrawal_claim_delay_secs: Option<u64>,
+    #[structopt(long = "watchtower")]
+    pub watchtower: bool,
 }
@@ -0,0 +1,81 @@
+use crate::checkpoint::EvmCursor;
+use crate::traits::TheaMessage;
+use ethers::types::H256;
+
+/// How deep a deposit log has to be before it is relayed to Polkadex
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DepositConfirmation {
+    /// Blocks mined on top of the block holding the log
+    Blocks(u64),
+    /// The `finalized` block tag of the chain
+    Finalized,
+}
+
+impl Default for DepositConfirmation {
+    fn default() -> Self {
+        DepositConfirmation::Blocks(DEFAULT_DEPOSIT_CONFIRMATIONS)
+    }
+}
+
+const DEFAULT_DEPOSIT_CONFIRMATIONS: u64 = 12;
+
+#[derive(Debug)]
+struct PendingDeposit {
+    block_hash: H256,
+    cursor: EvmCursor,
+    message: TheaMessage,
+}
+
+/// Deposit logs waiting for confirmations, kept in log order
+#[derive(Debug, Default)]
+pub struct PendingDeposits {
+    deposits: Vec<PendingDeposit>,
+}
+
+impl PendingDeposits {
+    /// A log is identified by its block hash and index, a reorg re-includes it under a new block hash
+    pub fn contains(&self, block_hash: H256, log_index: u64) -> bool {
+        self.deposits
+            .iter()
+            .any(|deposit| deposit.block_hash == block_hash && deposit.cursor.log_index == log_index)
+    }
+
+    pub fn push(&mut self, block_hash: H256, cursor: EvmCursor, message: TheaMessage) {
+        let position = self.deposits.partition_point(|deposit| deposit.cursor <= cursor);
+        self.deposits.insert(
+            position,
+            PendingDeposit {
+                block_hash,
+                cursor,
+                message,
+            },
+        );
+    }
+
+    /// Drops a deposit whose log was removed by a reorg, `false` if it was not pending
+    pub fn remove(&mut self, block_hash: H256, log_index: u64) -> bool {
+        let len = self.deposits.len();
+        self.deposits
+            .retain(|deposit| !(deposit.block_hash == block_hash && deposit.cursor.log_index == log_index));
+        self.deposits.len() != len
+    }
+
+    /// Takes the deposits at or below the confirmed block, in log order
+    pub fn release(&mut self, confirmed_block: u64) -> Vec<(EvmCursor, TheaMessage)> {
+        let position = self
+            .deposits
+            .partition_point(|deposit| deposit.cursor.block_number <= confirmed_block);
+        self.deposits
+            .drain(..position)
+            .map(|deposit| (deposit.cursor, deposit.message))
+            .collect()
+    }
+
+    pub fn first_block(&self) -> Option<u64> {
+        self.deposits.first().map(|deposit| deposit.cursor.block_number)
+    }
+
+    pub fn clear(&mut self) {
+        self.deposits.clear();
+    }
+}
@@ -6,6 +6,7 @@ use ethers::signers::{Wallet, WalletError};
 use k256::ecdsa::SigningKey;
 use tokio::sync::mpsc::error::SendError;
 use crate::traits::TheaMessage;
+use crate::substrateclient::polkadex;
 
 pub enum RelayerError {
     NativeError,
@@ -22,7 +23,72 @@ pub enum RelayerError {
     IoError(std::io::Error),
     SubxtSignerError(subxt_signer::ecdsa::Error),
     HexConversionError,
-    AuthoritiesNotFound
+    AuthoritiesNotFound,
+    SledError(sled::Error),
+    SerdeJsonError(serde_json::Error),
+    NetworkIdMismatch(u8, u8),
+    ChainIdMismatch(u64, u64),
+    InvalidKeyType(String),
+    SecretUriError(subxt_signer::SecretUriError),
+    SubxtSignerSr25519Error(subxt_signer::sr25519::Error),
+    EnvVarError(std::env::VarError),
+    KeystoreError(String),
+    RemoteSignerError(String),
+    ExtrinsicFailed(String),
+    TheaError(polkadex::thea::Error),
+    EvmTransactionReverted(String),
+    EvmTransactionStuck(u64),
+    GasPriceAboveCeiling(ethers::types::U256, ethers::types::U256),
+    NotEnoughSignatures(usize, usize),
+    UnknownValidatorSet(u64, u64),
+    OutgoingMessageNotFound(u64),
+    WithdrawalRejected(u64, String),
+}
+
+impl RelayerError {
+    /// Errors that restarting the failing task cannot recover from
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            RelayerError::TokioChannelError(_)
+                | RelayerError::SledError(_)
+                | RelayerError::SerdeJsonError(_)
+                | RelayerError::IoError(_)
+                // Every later withdrawal waits behind it, someone has to look at it
+                | RelayerError::WithdrawalRejected(_, _)
+        )
+    }
+
+    /// The incoming message was dispatched before the one holding the previous nonce landed
+    pub fn is_nonce_mismatch(&self) -> bool {
+        matches!(
+            self,
+            RelayerError::TheaError(polkadex::thea::Error::MessageNonce | polkadex::thea::Error::NonceError)
+        )
+    }
+
+    /// Errors caused by the node or the network, worth retrying for the same message
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RelayerError::SubxtError(error) => {
+                matches!(error, subxt::Error::Io(_) | subxt::Error::Rpc(_))
+            }
+            RelayerError::EthersContractError(error) => {
+                error.is_middleware_error() || error.is_provider_error()
+            }
+            // The previous nonce has not landed yet, the message goes out again once it has
+            RelayerError::TheaError(error) => {
+                matches!(error, polkadex::thea::Error::MessageNonce | polkadex::thea::Error::NonceError)
+            }
+            RelayerError::EthersSignerMiddlewareError(_)
+            | RelayerError::EthersProviderError(_)
+            | RelayerError::UnknownValidatorSet(_, _)
+            // The finalized block read may not have caught up with the message yet
+            | RelayerError::OutgoingMessageNotFound(_)
+            | RelayerError::UnableToFetchIncomingNonce => true,
+            _ => false,
+        }
+    }
 }
 
 impl Display for RelayerError {
@@ -43,6 +109,25 @@ impl Display for RelayerError {
             RelayerError::SubxtSignerError(error) => format!("Subxt Signer Error: {:?}", error),
             RelayerError::HexConversionError => "Hex Conversion Error".to_string(),
             RelayerError::AuthoritiesNotFound => "Authorities not found".to_string(),
+            RelayerError::SledError(error) => format!("Sled Error: {:?}", error),
+            RelayerError::SerdeJsonError(error) => format!("Serde Json Error: {:?}", error),
+            RelayerError::NetworkIdMismatch(configured, contract) => format!("Network Id Mismatch: configured {:?}, contract {:?}", configured, contract),
+            RelayerError::ChainIdMismatch(configured, node) => format!("Chain Id Mismatch: configured {:?}, node {:?}", configured, node),
+            RelayerError::InvalidKeyType(key_type) => format!("Invalid Key Type: {:?}", key_type),
+            RelayerError::SecretUriError(error) => format!("Secret Uri Error: {:?}", error),
+            RelayerError::SubxtSignerSr25519Error(error) => format!("Subxt Signer Sr25519 Error: {:?}", error),
+            RelayerError::EnvVarError(error) => format!("Env Var Error: {:?}", error),
+            RelayerError::KeystoreError(error) => format!("Keystore Error: {:?}", error),
+            RelayerError::RemoteSignerError(error) => format!("Remote Signer Error: {:?}", error),
+            RelayerError::ExtrinsicFailed(error) => format!("Extrinsic Failed: {:?}", error),
+            RelayerError::TheaError(error) => format!("Thea Error: {:?}", error),
+            RelayerError::EvmTransactionReverted(reason) => format!("Evm Transaction Reverted: {:?}", reason),
+            RelayerError::EvmTransactionStuck(nonce) => format!("Evm Transaction Stuck: nonce {:?}", nonce),
+            RelayerError::GasPriceAboveCeiling(price, ceiling) => format!("Gas Price Above Ceiling: {:?} > {:?}", price, ceiling),
+            RelayerError::NotEnoughSignatures(valid, threshold) => format!("Not Enough Signatures: {:?} valid, {:?} required", valid, threshold),
+            RelayerError::UnknownValidatorSet(id, known) => format!("Unknown Validator Set: {:?}, contract knows up to {:?}", id, known),
+            RelayerError::OutgoingMessageNotFound(nonce) => format!("Outgoing Message Not Found: nonce {:?}", nonce),
+            RelayerError::WithdrawalRejected(nonce, error) => format!("Withdrawal Rejected: nonce {:?}, {:?}", nonce, error),
         };
         write!(f, "{}", err_msg)
     }
@@ -66,6 +151,25 @@ impl Debug for RelayerError {
             RelayerError::SubxtSignerError(error) => format!("Subxt Signer Error: {}", error),
             RelayerError::HexConversionError => "Hex Conversion Error".to_string(),
             RelayerError::AuthoritiesNotFound => "Authorities not found".to_string(),
+            RelayerError::SledError(error) => format!("Sled Error: {}", error),
+            RelayerError::SerdeJsonError(error) => format!("Serde Json Error: {}", error),
+            RelayerError::NetworkIdMismatch(configured, contract) => format!("Network Id Mismatch: configured {}, contract {}", configured, contract),
+            RelayerError::ChainIdMismatch(configured, node) => format!("Chain Id Mismatch: configured {}, node {}", configured, node),
+            RelayerError::InvalidKeyType(key_type) => format!("Invalid Key Type: {}", key_type),
+            RelayerError::SecretUriError(error) => format!("Secret Uri Error: {}", error),
+            RelayerError::SubxtSignerSr25519Error(error) => format!("Subxt Signer Sr25519 Error: {}", error),
+            RelayerError::EnvVarError(error) => format!("Env Var Error: {}", error),
+            RelayerError::KeystoreError(error) => format!("Keystore Error: {}", error),
+            RelayerError::RemoteSignerError(error) => format!("Remote Signer Error: {}", error),
+            RelayerError::ExtrinsicFailed(error) => format!("Extrinsic Failed: {}", error),
+            RelayerError::TheaError(error) => format!("Thea Error: {:?}", error),
+            RelayerError::EvmTransactionReverted(reason) => format!("Evm Transaction Reverted: {}", reason),
+            RelayerError::EvmTransactionStuck(nonce) => format!("Evm Transaction Stuck: nonce {}", nonce),
+            RelayerError::GasPriceAboveCeiling(price, ceiling) => format!("Gas Price Above Ceiling: {} > {}", price, ceiling),
+            RelayerError::NotEnoughSignatures(valid, threshold) => format!("Not Enough Signatures: {} valid, {} required", valid, threshold),
+            RelayerError::UnknownValidatorSet(id, known) => format!("Unknown Validator Set: {}, contract knows up to {}", id, known),
+            RelayerError::OutgoingMessageNotFound(nonce) => format!("Outgoing Message Not Found: nonce {}", nonce),
+            RelayerError::WithdrawalRejected(nonce, error) => format!("Withdrawal Rejected: nonce {}, {}", nonce, error),
         };
         write!(f, "{}", err_msg)
     }
@@ -130,3 +234,32 @@ impl From<subxt_signer::ecdsa::Error> for RelayerError {
     }
 }
 
+impl From<sled::Error> for RelayerError {
+    fn from(value: sled::Error) -> Self {
+        Self::SledError(value)
+    }
+}
+
+impl From<serde_json::Error> for RelayerError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::SerdeJsonError(value)
+    }
+}
+
+impl From<subxt_signer::SecretUriError> for RelayerError {
+    fn from(value: subxt_signer::SecretUriError) -> Self {
+        Self::SecretUriError(value)
+    }
+}
+
+impl From<subxt_signer::sr25519::Error> for RelayerError {
+    fn from(value: subxt_signer::sr25519::Error) -> Self {
+        Self::SubxtSignerSr25519Error(value)
+    }
+}
+
+impl From<std::env::VarError> for RelayerError {
+    fn from(value: std::env::VarError) -> Self {
+        Self::EnvVarError(value)
+    }
+}
@@ -1,26 +1,48 @@
 use std::str::FromStr;
 use crate::traits::{EvmDeposit, Message, ObEvmDeposit, TheaMessage};
-use ethers::abi::{Address, Contract, Token};
+use ethers::abi::{Address, Contract, RawLog, Token};
 use ethers::contract::stream::EventStream;
 use ethers::contract::Contract as ContractType;
-use ethers::middleware::SignerMiddleware;
-use ethers::prelude::{Http, LocalWallet, Middleware, Signer, TransactionRequest, H256};
+use ethers::contract::{EthEvent, EthLogDecode, LogMeta};
+use ethers::prelude::{Http, Middleware, H256};
 use ethers::providers::Ws;
-use ethers::utils::{hex, keccak256};
+use ethers::utils::hex;
 use ethers::{
     contract::abigen,
     core::types::ValueOrArray,
     providers::{Provider, StreamExt},
 };
+use std::ops::{Range, RangeInclusive};
 use std::sync::Arc;
 use ethers::types::H160;
+use ethers::types::transaction::eip2718::TypedTransaction;
 use sp_application_crypto::RuntimeAppPublic;
 use sp_core::U256;
-use thea_primitives::ValidatorSetId;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{watch, Mutex};
 use vrf::openssl::{CipherSuite, ECVRF};
 use vrf::VRF;
 use crate::error::RelayerError;
+use crate::checkpoint::{CheckpointStore, EvmCursor, EvmStream};
+use crate::evmprovider::{ConnectionState, ReconnectingProvider};
+use crate::evmsigner::RelayerSigner;
+use crate::feestrategy::FeeStrategy;
+use crate::confirmation::{DepositConfirmation, PendingDeposits};
+use crate::checkpoint::{EvmTxOutcome, EvmTxStatus};
+use ethers::contract::{ContractError, ContractRevert};
+use ethers::providers::{ProviderError, RpcError};
+use ethers::types::{BlockNumber, TransactionReceipt};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a transaction may stay unmined before it is replaced with a higher fee
+const STUCK_TX_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_FEE_BUMPS: u32 = 5;
+/// How often pending deposits are checked against the confirmation policy
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the network price is checked again while it is above the ceiling
+const GAS_CEILING_POLL_INTERVAL: Duration = Duration::from_secs(30);
 
 // abigen!(
 //     AggregatorInterface,
@@ -39,11 +61,29 @@ use crate::error::RelayerError;
 #[derive(Clone, Debug)]
 pub struct EvmClient {
     url: String,
-    provider: Provider<Ws>,
+    provider: ReconnectingProvider,
     contract: Contract,
-    thea_contract: TheaContract<Provider<Ws>>,
-    wallet: LocalWallet,
-    contract_address: Address
+    signer: Arc<dyn RelayerSigner>,
+    contract_address: Address,
+    network_id: u8,
+    chain_id: u64,
+    start_block: Option<u64>,
+    log_page_size: u64,
+    tx_confirmations: u64,
+    fee_strategy: FeeStrategy,
+    deposit_confirmation: DepositConfirmation,
+    // Shared by every clone so the relay loop, claimer and watchtower take signer nonces one after another
+    tx_lock: Arc<Mutex<()>>,
+    // Normal transactions line up here first, so an urgent one only waits for the broadcast in progress
+    queue_lock: Arc<Mutex<()>>,
+}
+
+/// Whether a transaction waits for its turn and for the gas price to drop under the ceiling
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TxPriority {
+    Normal,
+    /// Watchtower transactions, they have to land before the withdrawal can be claimed
+    Urgent,
 }
 
 abigen!(
@@ -52,80 +92,586 @@ abigen!(
     event_derives(serde::Deserialize, serde::Serialize)
 );
 
+/// Withdrawal stored by the contract once its message is processed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PendingWithdrawal {
+    pub index: u64,
+    pub asset_id: u128,
+    pub amount: u128,
+    pub recipient: Address,
+    pub is_blocked: bool,
+}
+
+/// Decodes revert data of the Thea contract into its custom error or revert string
+pub fn decode_revert(data: &[u8]) -> String {
+    match TheaContractErrors::decode_with_selector(data) {
+        Some(error) => format!("{:?}", error),
+        None => format!("0x{}", hex::encode(data)),
+    }
+}
+
+/// Deposit amount as Polkadex holds it, `None` for a log whose amount does not fit so it is skipped
+/// instead of stopping the stream
+pub fn deposit_amount(amount: ethers::types::U256, meta: &LogMeta) -> Option<u128> {
+    match u128::try_from(amount) {
+        Ok(amount) => Some(amount),
+        Err(_) => {
+            println!("ALERT: skipping deposit in tx {:?} log {:?}, amount {:?} does not fit in u128", meta.transaction_hash, meta.log_index, amount);
+            None
+        }
+    }
+}
+
+fn revert_reason(err: &ProviderError) -> Option<String> {
+    let revert_data = err.as_error_response()?.as_revert_data()?;
+    Some(decode_revert(&revert_data))
+}
+
 impl EvmClient {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         url: String,
         contract: Contract,
-        seed: String,
-        contract_address: String
+        signer: Arc<dyn RelayerSigner>,
+        contract_address: String,
+        network_id: u8,
+        chain_id: Option<u64>,
+        start_block: Option<u64>,
+        log_page_size: u64,
+        tx_confirmations: u64,
+        fee_strategy: FeeStrategy,
+        deposit_confirmation: DepositConfirmation
     ) -> Result<Self, RelayerError> {
-        let provider = Provider::<Ws>::connect(url.clone()).await?;
-        let wallet: LocalWallet = seed.as_str().parse()?;
-        let wallet = wallet.with_chain_id(11155111u64);
-        let thea_contract = TheaContract::new(contract_address.parse::<Address>().map_err(|_| RelayerError::HexConversionError)?, provider.clone().into());
-        Ok(Self {
+        let provider = ReconnectingProvider::connect(url.clone()).await?;
+        let node_chain_id = provider.provider().await.1.get_chainid().await?.as_u64();
+        if let Some(chain_id) = chain_id {
+            if chain_id != node_chain_id {
+                return Err(RelayerError::ChainIdMismatch(chain_id, node_chain_id));
+            }
+        }
+        println!("EVM chain id {:?}", node_chain_id);
+        println!("EVM relayer account {:?}", signer.address());
+        let client = Self {
             url,
             provider,
             contract,
-            wallet,
-            thea_contract,
-            contract_address: contract_address.parse().map_err(|_| RelayerError::HexConversionError)?
-        })
+            signer,
+            contract_address: contract_address.parse().map_err(|_| RelayerError::HexConversionError)?,
+            network_id,
+            chain_id: node_chain_id,
+            start_block,
+            log_page_size: log_page_size.max(1),
+            tx_confirmations: tx_confirmations.max(1),
+            fee_strategy,
+            deposit_confirmation,
+            tx_lock: Arc::new(Mutex::new(())),
+            queue_lock: Arc::new(Mutex::new(())),
+        };
+        let contract_network_id: u8 = client.thea_contract().await.network_id().call().await?;
+        if contract_network_id != network_id {
+            return Err(RelayerError::NetworkIdMismatch(network_id, contract_network_id));
+        }
+        Ok(client)
+    }
+
+    pub fn network_id(&self) -> u8 {
+        self.network_id
     }
 
     pub async fn subscribe_deposit_events_stream(
         &self,
         sender: UnboundedSender<TheaMessage>,
+        checkpoint: CheckpointStore,
     ) -> Result<(), RelayerError> {
         println!("Subscribed deposit events");
-        let event =
-            ContractType::event_of_type::<DepositEventFilter>(Arc::new(self.provider.clone()))
-                .address(ValueOrArray::Array(vec![
-                    self.contract_address,
-                ]));
-        let mut stream = event.subscribe_with_meta().await?.take(2);
-        while let Some(Ok((event, _meta))) = stream.next().await {
+        self.relay_events::<DepositEventFilter, _>(EvmStream::Deposit, sender, checkpoint, |event, meta| {
             println!("Got Deposit Event");
+            let amount = deposit_amount(event.amount, meta)?;
             let deposit = EvmDeposit::new(
-                event.recipient.clone().to_vec(),
-                event.asset_id.clone(),
-                event.amount.clone().as_u128(),
-                event.outgoing_nonce
+                event.recipient.to_vec(),
+                event.asset_id,
+                amount,
+                event.outgoing_nonce,
+                meta.block_number.as_u64()
             );
-            sender.send(TheaMessage::EvmDeposit(deposit))?;
-        }
-        Ok(())
+            Some(TheaMessage::EvmDeposit(deposit))
+        })
+        .await
     }
 
     pub async fn subscribe_ob_deposit_events_stream(
         &self,
         sender: UnboundedSender<TheaMessage>,
+        checkpoint: CheckpointStore,
     ) -> Result<(), RelayerError> {
-        let event =
-            ContractType::event_of_type::<DepositEventObFilter>(Arc::new(self.provider.clone()))
-                .address(ValueOrArray::Array(vec![
-                    self.contract_address,
-                ]));
-        let mut stream = event.subscribe_with_meta().await?.take(2);
-        while let Some(Ok((event, _meta))) = stream.next().await {
+        self.relay_events::<DepositEventObFilter, _>(EvmStream::ObDeposit, sender, checkpoint, |event, meta| {
+            let amount = deposit_amount(event.amount, meta)?;
+            let id = [meta.transaction_hash.as_bytes(), &meta.log_index.as_u64().to_be_bytes()].concat();
             let deposit = ObEvmDeposit::new(
-                event.main_account.clone().to_vec(),
-                event.trading_account.clone().to_vec(),
-                event.asset_id.clone(),
-                event.amount.clone().as_u128(),
+                id,
+                event.main_account.to_vec(),
+                event.trading_account.to_vec(),
+                event.asset_id,
+                amount,
+                meta.block_number.as_u64(),
+            );
+            Some(TheaMessage::ObEvmDeposit(deposit))
+        })
+        .await
+    }
+
+    /// Backfills logs missed since the last checkpoint and then follows the live subscription,
+    /// relaying deposits once they are confirmed and dropping the ones removed by a reorg
+    async fn relay_events<D, F>(
+        &self,
+        stream: EvmStream,
+        sender: UnboundedSender<TheaMessage>,
+        checkpoint: CheckpointStore,
+        to_message: F,
+    ) -> Result<(), RelayerError>
+    where
+        D: EthEvent,
+        F: Fn(D, &LogMeta) -> Option<TheaMessage>,
+    {
+        let mut last_cursor = checkpoint.evm_cursor(stream)?;
+        let mut from_block = last_cursor.map(|cursor| cursor.block_number).or(self.start_block);
+        let mut pending = PendingDeposits::default();
+        loop {
+            // Unconfirmed deposits are read again by the backfill after reconnecting
+            pending.clear();
+            let (generation, provider) = self.provider.provider().await;
+            // Subscribe before backfilling so nothing emitted in between is missed
+            let event = ContractType::event_of_type::<D>(Arc::new(provider.clone()))
+                .address(ValueOrArray::Array(vec![self.contract_address]));
+            let mut live_stream = match provider.subscribe_logs(&event.filter).await {
+                Ok(live_stream) => live_stream,
+                Err(err) => {
+                    println!("Failed to subscribe to {:?} logs: {:?}", stream, err);
+                    self.provider.reconnect(generation).await;
+                    continue;
+                }
+            };
+            let head = match provider.get_block_number().await {
+                Ok(head) => head.as_u64(),
+                Err(err) => {
+                    println!("Failed to fetch EVM head: {:?}", err);
+                    self.provider.reconnect(generation).await;
+                    continue;
+                }
+            };
+            if let Some(start_block) = from_block {
+                match self
+                    .backfill_events(&provider, stream, &to_message, &mut pending, last_cursor, start_block..=head)
+                    .await
+                {
+                    Ok(()) => {}
+                    Err(RelayerError::EthersContractError(err)) => {
+                        // Backfill resumes from the last relayed cursor after reconnecting
+                        println!("Failed to backfill {:?} logs: {:?}", stream, err);
+                        self.provider.reconnect(generation).await;
+                        from_block = Some(last_cursor.map_or(start_block, |cursor| cursor.block_number));
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            let mut confirmations = tokio::time::interval(CONFIRMATION_POLL_INTERVAL);
+            loop {
+                tokio::select! {
+                    log = live_stream.next() => {
+                        let Some(log) = log else {
+                            break;
+                        };
+                        let meta = LogMeta::from(&log);
+                        let cursor = EvmCursor::new(meta.block_number.as_u64(), meta.log_index.as_u64());
+                        if log.removed == Some(true) {
+                            if pending.remove(meta.block_hash, cursor.log_index) {
+                                println!("Dropped {:?} log at {:?} removed by a reorg", stream, cursor);
+                            } else if last_cursor.map_or(false, |last_cursor| cursor <= last_cursor) {
+                                println!("Relayed {:?} log at {:?} was removed by a reorg deeper than the confirmation depth", stream, cursor);
+                            }
+                            continue;
+                        }
+                        // Already pending, relayed by the backfill or before the last restart
+                        if last_cursor.map_or(false, |last_cursor| cursor <= last_cursor)
+                            || pending.contains(meta.block_hash, cursor.log_index)
+                        {
+                            continue;
+                        }
+                        let event = match D::decode_log(&RawLog::from(log)) {
+                            Ok(event) => event,
+                            Err(err) => {
+                                println!("Failed to decode {:?} log: {:?}", stream, err);
+                                continue;
+                            }
+                        };
+                        if let Some(message) = to_message(event, &meta) {
+                            pending.push(meta.block_hash, cursor, message);
+                        }
+                    }
+                    _ = confirmations.tick() => {
+                        match self.confirmed_block(&provider).await {
+                            Ok(confirmed_block) => {
+                                Self::release_deposits(stream, &sender, &checkpoint, &mut pending, &mut last_cursor, confirmed_block)?;
+                            }
+                            Err(err) => {
+                                println!("Failed to fetch confirmed EVM block: {:?}", err);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            println!("{:?} subscription ended, resubscribing", stream);
+            self.provider.reconnect(generation).await;
+            // Catch up on anything emitted while the subscription was down and on deposits still pending
+            from_block = Some(
+                last_cursor
+                    .map(|cursor| cursor.block_number)
+                    .or(pending.first_block())
+                    .unwrap_or(head),
             );
-            sender.send(TheaMessage::ObEvmDeposit(deposit))?;
+        }
+    }
+
+    /// Latest block deposits may be relayed from under the confirmation policy
+    async fn confirmed_block(&self, provider: &Provider<Ws>) -> Result<u64, RelayerError> {
+        match self.deposit_confirmation {
+            DepositConfirmation::Blocks(depth) => Ok(provider.get_block_number().await?.as_u64().saturating_sub(depth)),
+            DepositConfirmation::Finalized => Ok(provider
+                .get_block(BlockNumber::Finalized)
+                .await?
+                .and_then(|block| block.number)
+                .map_or(0, |number| number.as_u64())),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn backfill_events<D, F>(
+        &self,
+        provider: &Provider<Ws>,
+        stream: EvmStream,
+        to_message: &F,
+        pending: &mut PendingDeposits,
+        last_cursor: Option<EvmCursor>,
+        blocks: RangeInclusive<u64>,
+    ) -> Result<(), RelayerError>
+    where
+        D: EthEvent,
+        F: Fn(D, &LogMeta) -> Option<TheaMessage>,
+    {
+        let (mut from_block, head) = blocks.into_inner();
+        println!("Backfilling {:?} logs from block {:?} to {:?}", stream, from_block, head);
+        while from_block <= head {
+            let to_block = from_block.saturating_add(self.log_page_size.saturating_sub(1)).min(head);
+            let logs = ContractType::event_of_type::<D>(Arc::new(provider.clone()))
+                .address(ValueOrArray::Array(vec![self.contract_address]))
+                .from_block(from_block)
+                .to_block(to_block)
+                .query_with_meta()
+                .await?;
+            for (event, meta) in logs {
+                let cursor = EvmCursor::new(meta.block_number.as_u64(), meta.log_index.as_u64());
+                if last_cursor.map_or(false, |last_cursor| cursor <= last_cursor)
+                    || pending.contains(meta.block_hash, cursor.log_index)
+                {
+                    continue;
+                }
+                if let Some(message) = to_message(event, &meta) {
+                    pending.push(meta.block_hash, cursor, message);
+                }
+            }
+            from_block = to_block.saturating_add(1);
+        }
+        Ok(())
+    }
+
+    fn release_deposits(
+        stream: EvmStream,
+        sender: &UnboundedSender<TheaMessage>,
+        checkpoint: &CheckpointStore,
+        pending: &mut PendingDeposits,
+        last_cursor: &mut Option<EvmCursor>,
+        confirmed_block: u64,
+    ) -> Result<(), RelayerError> {
+        for (cursor, message) in pending.release(confirmed_block) {
+            Self::relay_event(stream, sender, checkpoint, message, cursor)?;
+            *last_cursor = Some(cursor);
         }
         Ok(())
     }
 
+    fn relay_event(
+        stream: EvmStream,
+        sender: &UnboundedSender<TheaMessage>,
+        checkpoint: &CheckpointStore,
+        message: TheaMessage,
+        cursor: EvmCursor,
+    ) -> Result<(), RelayerError> {
+        checkpoint.add_in_flight(&message)?;
+        checkpoint.set_evm_cursor(stream, cursor)?;
+        sender.send(message)?;
+        Ok(())
+    }
+
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.provider.state()
+    }
+
+    async fn thea_contract(&self) -> TheaContract<Provider<Ws>> {
+        let (_, provider) = self.provider.provider().await;
+        TheaContract::new(self.contract_address, Arc::new(provider))
+    }
+
+    /// Last Substrate outgoing nonce processed by the Thea contract
+    pub async fn get_incoming_nonce(&self) -> Result<u64, RelayerError> {
+        let incoming_nonce: u64 = self.thea_contract().await.incoming_nonce().call().await?;
+        Ok(incoming_nonce)
+    }
+
+    /// Block to read logs from when no checkpoint exists yet
+    pub fn start_block(&self) -> Option<u64> {
+        self.start_block
+    }
+
+    pub async fn block_number(&self) -> Result<u64, RelayerError> {
+        let (_, provider) = self.provider.provider().await;
+        Ok(provider.get_block_number().await?.as_u64())
+    }
+
+    pub async fn block_timestamp(&self, block_number: u64) -> Result<u64, RelayerError> {
+        let (_, provider) = self.provider.provider().await;
+        Ok(provider
+            .get_block(block_number)
+            .await?
+            .map_or(0, |block| block.timestamp.as_u64()))
+    }
+
+    /// Nonces of the messages processed in the block range, along with their block number
+    pub async fn processed_messages(&self, blocks: RangeInclusive<u64>) -> Result<Vec<(u64, u64)>, RelayerError> {
+        let logs = self.query_logs::<MessageProcessedFilter>(blocks).await?;
+        Ok(logs
+            .into_iter()
+            .map(|(event, meta)| (event.nonce, meta.block_number.as_u64()))
+            .collect())
+    }
+
+    /// Message nonce and withdrawal index of every withdrawal claimed in the block range
+    pub async fn claimed_withdrawals(&self, blocks: RangeInclusive<u64>) -> Result<Vec<(u64, u64)>, RelayerError> {
+        let logs = self.query_logs::<WithdrawalClaimedFilter>(blocks).await?;
+        Ok(logs
+            .into_iter()
+            .map(|(event, _)| (event.message_id, event.withdrawal_index))
+            .collect())
+    }
+
+    async fn query_logs<D: EthEvent>(&self, blocks: RangeInclusive<u64>) -> Result<Vec<(D, LogMeta)>, RelayerError> {
+        let contract = self.thea_contract().await;
+        let (mut from_block, head) = blocks.into_inner();
+        let mut logs = vec![];
+        while from_block <= head {
+            let to_block = from_block.saturating_add(self.log_page_size.saturating_sub(1)).min(head);
+            logs.extend(
+                contract
+                    .event::<D>()
+                    .from_block(from_block)
+                    .to_block(to_block)
+                    .query_with_meta()
+                    .await?,
+            );
+            from_block = to_block.saturating_add(1);
+        }
+        Ok(logs)
+    }
+
+    /// Withdrawal the contract stored at the index of a processed message, `None` past the end
+    pub async fn pending_withdrawal(&self, nonce: u64, index: u64) -> Result<Option<PendingWithdrawal>, RelayerError> {
+        match self.thea_contract().await.pending_withdrawals(nonce, U256::from(index)).call().await {
+            Ok((_, asset_id, amount, recipient, is_blocked, _)) => Ok(Some(PendingWithdrawal {
+                index,
+                asset_id,
+                amount,
+                recipient,
+                is_blocked,
+            })),
+            Err(ContractError::Revert(_)) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Withdrawals the contract stored for a processed message at the given indexes. The contract has no
+    /// length view, callers bound the range with the withdrawal count signed on Polkadex.
+    pub async fn pending_withdrawals(&self, nonce: u64, indexes: Range<u64>) -> Result<Vec<PendingWithdrawal>, RelayerError> {
+        let mut withdrawals = vec![];
+        for index in indexes {
+            match self.pending_withdrawal(nonce, index).await? {
+                Some(withdrawal) => withdrawals.push(withdrawal),
+                None => break,
+            }
+        }
+        Ok(withdrawals)
+    }
+
+    /// Stops a pending withdrawal from being claimed
+    pub async fn block_transaction(&self, nonce: u64, index: u64) -> Result<EvmTxOutcome, RelayerError> {
+        let data = self
+            .contract
+            .function("blockTransaction")?
+            .encode_input(&[Token::Uint(U256::from(nonce)), Token::Uint(U256::from(index))])?;
+        let tx = self.fee_strategy.transaction(self.contract_address, data.into());
+        let outcome = self.send_transaction(tx, TxPriority::Urgent).await?;
+        println!("Block withdrawal {:?} of message {:?} tx {:?}", index, nonce, outcome);
+        Ok(outcome)
+    }
+
+    pub async fn claim_withdrawal(&self, nonce: u64, index: u64) -> Result<EvmTxOutcome, RelayerError> {
+        let data = self
+            .contract
+            .function("claimWithdrawal")?
+            .encode_input(&[Token::Uint(U256::from(nonce)), Token::Uint(U256::from(index))])?;
+        let tx = self.fee_strategy.transaction(self.contract_address, data.into());
+        let outcome = self.send_transaction(tx, TxPriority::Normal).await?;
+        println!("Claim withdrawal {:?} of message {:?} tx {:?}", index, nonce, outcome);
+        Ok(outcome)
+    }
+
+    /// Highest validator set the contract accepts proofs from, the scheduled one once a rotation was relayed
+    pub async fn known_validator_set_id(&self) -> Result<u64, RelayerError> {
+        let contract = self.thea_contract().await;
+        let latest: u64 = contract.latest_validator_set_id().call().await?;
+        let next: u64 = contract.next_validator_set_id().call().await?;
+        Ok(latest.max(next))
+    }
+
+    /// Signs and broadcasts the transaction, bumping the fee of stuck replacements until one is confirmed
+    async fn send_transaction(&self, mut tx: TypedTransaction, priority: TxPriority) -> Result<EvmTxOutcome, RelayerError> {
+        let (_, provider) = self.provider.provider().await;
+        tx.set_from(self.signer.address());
+        tx.set_chain_id(self.chain_id);
+        loop {
+            match self.fee_strategy.apply(&provider, &mut tx, priority == TxPriority::Normal).await {
+                // Nothing is sent until the network price drops back under the ceiling, no lock is held meanwhile
+                Err(RelayerError::GasPriceAboveCeiling(price, ceiling)) => {
+                    println!("Gas price {:?} is above the ceiling {:?}, pausing submission", price, ceiling);
+                    tokio::time::sleep(GAS_CEILING_POLL_INTERVAL).await;
+                }
+                // Gas estimation runs the call, so a reverting message fails here before it is broadcast
+                Err(RelayerError::EthersProviderError(err)) => {
+                    return match revert_reason(&err) {
+                        Some(reason) => Err(RelayerError::EvmTransactionReverted(reason)),
+                        None => Err(err.into()),
+                    };
+                }
+                result => break result?,
+            }
+        }
+        let queue_guard = match priority {
+            TxPriority::Normal => Some(self.queue_lock.lock().await),
+            TxPriority::Urgent => None,
+        };
+        let tx_guard = self.tx_lock.lock().await;
+        // Read under the lock so the previous transaction is already counted among the pending ones
+        let nonce = provider
+            .get_transaction_count(self.signer.address(), Some(BlockNumber::Pending.into()))
+            .await?;
+        tx.set_nonce(nonce);
+        let nonce = nonce.as_u64();
+        let mut guards = Some((queue_guard, tx_guard));
+        let mut tx_hashes: Vec<H256> = vec![];
+        let mut bumps = 0;
+        loop {
+            let raw_tx = self.signer.sign_transaction(&tx).await?;
+            // Once broadcast, everything goes through the current provider so a reconnect does not lose the tx
+            let (_, provider) = self.provider.provider().await;
+            match provider.send_raw_transaction(raw_tx).await {
+                Ok(pending_tx) => {
+                    println!("Sent tx {:?} with nonce {:?}", pending_tx.tx_hash(), nonce);
+                    tx_hashes.push(pending_tx.tx_hash());
+                }
+                // An earlier replacement may have been mined in the meantime
+                Err(err) if !tx_hashes.is_empty() => println!("Failed to replace tx with nonce {:?}: {:?}", nonce, err),
+                Err(err) => return Err(err.into()),
+            }
+            // The node counts the broadcast tx as pending now, the next sender can read its nonce
+            guards.take();
+            if let Some(receipt) = self.wait_for_receipt(&tx_hashes).await {
+                if let Some(receipt) = self.wait_for_confirmations(receipt).await {
+                    let status = if receipt.status == Some(1u64.into()) {
+                        EvmTxStatus::Confirmed(receipt.block_number.map_or(0, |number| number.as_u64()))
+                    } else {
+                        // Replay the call at the receipt's block to recover the revert data
+                        let (_, provider) = self.provider.provider().await;
+                        let reason = match provider.call(&tx, receipt.block_number.map(Into::into)).await {
+                            Err(err) => revert_reason(&err),
+                            Ok(_) => None,
+                        };
+                        EvmTxStatus::Reverted(reason.unwrap_or_else(|| "Unknown revert reason".to_string()))
+                    };
+                    return Ok(EvmTxOutcome::new(nonce, &tx_hashes, status));
+                }
+                // Receipt vanished in a reorg, keep waiting on the same replacements
+                continue;
+            }
+            if bumps == MAX_FEE_BUMPS || !self.fee_strategy.bump(&mut tx) {
+                return Ok(EvmTxOutcome::new(nonce, &tx_hashes, EvmTxStatus::Stuck));
+            }
+            println!("Tx with nonce {:?} is stuck, replacing it with higher fees", nonce);
+            bumps += 1;
+        }
+    }
+
+    /// Polls for a receipt of any replacement, `None` when nothing got mined in time
+    async fn wait_for_receipt(&self, tx_hashes: &[H256]) -> Option<TransactionReceipt> {
+        let deadline = Instant::now() + STUCK_TX_TIMEOUT;
+        loop {
+            let (generation, provider) = self.provider.provider().await;
+            for tx_hash in tx_hashes {
+                match provider.get_transaction_receipt(*tx_hash).await {
+                    Ok(Some(receipt)) => return Some(receipt),
+                    Ok(None) => {}
+                    // The replacements are already broadcast, keep polling them once reconnected
+                    Err(err) => {
+                        println!("Failed to fetch receipt of tx {:?}: {:?}", tx_hash, err);
+                        self.provider.reconnect(generation).await;
+                        break;
+                    }
+                }
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waits until the receipt is buried deep enough and checks it is still part of the chain
+    async fn wait_for_confirmations(&self, receipt: TransactionReceipt) -> Option<TransactionReceipt> {
+        let block_number = receipt.block_number.map_or(0, |number| number.as_u64());
+        loop {
+            let (generation, provider) = self.provider.provider().await;
+            let confirmed = match provider.get_block_number().await {
+                Ok(head) if head.as_u64().saturating_add(1) >= block_number.saturating_add(self.tx_confirmations) => {
+                    provider.get_transaction_receipt(receipt.transaction_hash).await.map(Some)
+                }
+                Ok(_) => Ok(None),
+                Err(err) => Err(err),
+            };
+            match confirmed {
+                Ok(Some(receipt)) => return receipt,
+                Ok(None) => tokio::time::sleep(RECEIPT_POLL_INTERVAL).await,
+                Err(err) => {
+                    println!("Failed to confirm tx {:?}: {:?}", receipt.transaction_hash, err);
+                    self.provider.reconnect(generation).await;
+                }
+            }
+        }
+    }
+
     pub async fn handle_substrate_message(
         &self,
         message: Vec<u8>,
-    ) -> Result<(), RelayerError> {
-        let signature = self
-            .wallet
-            .sign_hash(H256::from(keccak256(message.clone())))?;
+    ) -> Result<EvmTxOutcome, RelayerError> {
+        let signature = self.signer.sign_message(&message).await?;
         let signature = signature.to_vec();
         let signature = Token::Bytes(signature);
         let message_token = Token::Bytes(message);
@@ -134,55 +680,60 @@ impl EvmClient {
             .contract
             .function("sendMessage")?
             .encode_input(&token_array)?;
-        let tx = TransactionRequest::new();
-        let tx = tx.to(self.contract_address).data(data).chain_id(11155111);
-        let mut client = SignerMiddleware::new(self.provider.clone(), self.wallet.clone());
-        let pending_tx = client.send_transaction(tx, None).await?;
-        println!("Pending tx_id {:?}", pending_tx);
-        Ok(())
+        let tx = self.fee_strategy.transaction(self.contract_address, data.into());
+        let outcome = self.send_transaction(tx, TxPriority::Normal).await?;
+        println!("Substrate message tx {:?}", outcome);
+        Ok(outcome)
     }
 
     pub async fn get_validator_index(&self, message: Vec<u8>, validator_set_id: u64, indexes: Vec<u64>) -> Result<Vec<u64>, RelayerError> {
-        let indexes: Vec<u64> = self.thea_contract.get_validator_index(message.into(), validator_set_id.into(), indexes).call().await?;
+        let indexes: Vec<u64> = self.thea_contract().await.get_validator_index(message.into(), validator_set_id.into(), indexes).call().await?;
         Ok(indexes)
     }
 
-    pub async fn handle_substrate_message_with_proof(&self, message: Vec<u8>, validator_set_id: ValidatorSetId ,signatures: Vec<(u32, sp_core::ecdsa::Signature)>) -> Result<(), RelayerError> {
-
-        println!("Got Message from Substrate");
-        println!("Substrate Message is: {:?}", hex::encode(message.clone()));
-        let signature_indexes: Vec<u64> = signatures.iter().map(|(index, _)| *index as u64).collect();
-        println!("signatue_index KSR {:?}", signature_indexes.clone());
-        let indexes: Vec<u64> = self.get_validator_index(message.clone(), validator_set_id, signature_indexes.clone()).await?;
-        println!("Indexes {:?}", indexes.clone());
-        let mut final_signatures: Vec<Token> = vec![];
-        println!("Raw Sig Len {:?}", signatures.len());
-        for i in indexes.clone() {
-            for (index, sig) in signatures.clone() {
-                if i == index as u64 {
-                    let sig = sig.0.to_vec();
-                    println!("Indexed Signature {:?}", hex::encode(sig.clone()));
-                    final_signatures.push(Token::Bytes(sig));
-                }
-            }
+    /// Validators whose signatures the contract will check for the proof, with the addresses it holds for them,
+    /// along with how many signatures it requires
+    pub async fn verification_validators(
+        &self,
+        message: Vec<u8>,
+        validator_set_id: u64,
+        indexes: Vec<u64>,
+    ) -> Result<(Vec<(u64, Address)>, usize), RelayerError> {
+        let contract = self.thea_contract().await;
+        let mut validators = vec![];
+        for index in self.get_validator_index(message, validator_set_id, indexes).await? {
+            let validator: Address = contract.validators(validator_set_id.into(), index.into()).call().await?;
+            validators.push((index, validator));
         }
-        println!("Final Signature Len {:?}", final_signatures.len());
-        //println!("Final Sig {:?}", final_signatures.clone());
-        let signatures = Token::Array(final_signatures);
-        let message_token = Token::Bytes(message);
-        let signature_indexes_token: Vec<Token> = signature_indexes.iter().map(|index| Token::Uint(U256::from(*index))).collect();
-        println!("Signature Indexes Len {:?}", signature_indexes_token.len());
-        let siganture_indexes = Token::Array(signature_indexes_token);
-        let token_array = vec![message_token, signatures, siganture_indexes];
-        let data = self
-            .contract
-            .function("sendMessage")?
-            .encode_input(&token_array)?;
-        let tx = TransactionRequest::new();
-        let tx = tx.to(self.contract_address).data(data).chain_id(11155111); //TODO: Make it part
-        let client = SignerMiddleware::new(self.provider.clone(), self.wallet.clone());
-        let pending_tx = client.send_transaction(tx, None).await?;
-        println!("pending_tx: {:?}", pending_tx);
-        Ok(())
+        let index_size: u64 = contract.index_size().call().await?;
+        Ok((validators, index_size as usize))
+    }
+
+    /// Sends the message with the signatures of the validators the proof was verified against, `indexes` being
+    /// the ones `verification_validators` returned
+    pub async fn handle_substrate_message_with_proof(
+        &self,
+        message: Vec<u8>,
+        indexes: &[u64],
+        signatures: Vec<(u32, sp_core::ecdsa::Signature)>,
+    ) -> Result<EvmTxOutcome, RelayerError> {
+        let signature_indexes: Vec<Token> = signatures
+            .iter()
+            .map(|(index, _)| Token::Uint(U256::from(*index)))
+            .collect();
+        let verified_signatures: Vec<Token> = indexes
+            .iter()
+            .filter_map(|index| signatures.iter().find(|(signature_index, _)| u64::from(*signature_index) == *index))
+            .map(|(_, signature)| Token::Bytes(signature.0.to_vec()))
+            .collect();
+        let data = self.contract.function("sendMessage")?.encode_input(&[
+            Token::Bytes(message),
+            Token::Array(verified_signatures),
+            Token::Array(signature_indexes),
+        ])?;
+        let tx = self.fee_strategy.transaction(self.contract_address, data.into());
+        let outcome = self.send_transaction(tx, TxPriority::Normal).await?;
+        println!("Substrate message tx {:?}", outcome);
+        Ok(outcome)
     }
 }
@@ -0,0 +1,73 @@
+use crate::error::RelayerError;
+use ethers::providers::{Provider, Ws};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex, RwLock};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting,
+}
+
+/// WebSocket provider that can be replaced in place when the connection drops
+#[derive(Clone, Debug)]
+pub struct ReconnectingProvider {
+    url: String,
+    // Generation is bumped on every reconnect so concurrent callers reconnect only once
+    provider: Arc<RwLock<(u64, Provider<Ws>)>>,
+    reconnect_lock: Arc<Mutex<()>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+}
+
+impl ReconnectingProvider {
+    pub async fn connect(url: String) -> Result<Self, RelayerError> {
+        let provider = Provider::<Ws>::connect(url.clone()).await?;
+        let (state, _) = watch::channel(ConnectionState::Connected);
+        Ok(Self {
+            url,
+            provider: Arc::new(RwLock::new((0, provider))),
+            reconnect_lock: Arc::new(Mutex::new(())),
+            state: Arc::new(state),
+        })
+    }
+
+    /// Current provider along with its generation
+    pub async fn provider(&self) -> (u64, Provider<Ws>) {
+        self.provider.read().await.clone()
+    }
+
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Replaces the provider of the given generation, retrying with exponential backoff until connected
+    pub async fn reconnect(&self, generation: u64) {
+        let _guard = self.reconnect_lock.lock().await;
+        if self.provider.read().await.0 != generation {
+            // Another task already reconnected
+            return;
+        }
+        self.state.send_replace(ConnectionState::Reconnecting);
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            println!("Reconnecting to EVM node {:?}", self.url);
+            match Provider::<Ws>::connect(self.url.clone()).await {
+                Ok(provider) => {
+                    *self.provider.write().await = (generation.saturating_add(1), provider);
+                    self.state.send_replace(ConnectionState::Connected);
+                    println!("Reconnected to EVM node");
+                    return;
+                }
+                Err(err) => {
+                    println!("Failed to reconnect to EVM node: {:?}, retrying in {:?}", err, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::error::RelayerError;
+use async_trait::async_trait;
+use ethers::prelude::{Http, LocalWallet, Signer};
+use ethers::providers::Provider;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Signature, H256};
+use ethers::utils::{hex, keccak256};
+use serde::Deserialize;
+use std::fmt::Debug;
+
+/// Signs hashes and transactions on behalf of the EVM client
+#[async_trait]
+pub trait RelayerSigner: Debug + Send + Sync {
+    fn address(&self) -> Address;
+
+    /// Signs the keccak256 hash of the message as is, without the EIP-191 prefix
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, RelayerError>;
+
+    /// Returns the RLP encoded signed transaction, ready for `eth_sendRawTransaction`
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Bytes, RelayerError>;
+}
+
+/// Signs with a private key held by the relayer process
+#[derive(Clone, Debug)]
+pub struct LocalSigner {
+    wallet: LocalWallet,
+}
+
+impl LocalSigner {
+    pub fn new(wallet: LocalWallet) -> Self {
+        Self { wallet }
+    }
+
+    pub fn from_seed(seed: &str) -> Result<Self, RelayerError> {
+        Ok(Self::new(seed.parse()?))
+    }
+}
+
+#[async_trait]
+impl RelayerSigner for LocalSigner {
+    fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, RelayerError> {
+        Ok(self.wallet.sign_hash(H256::from(keccak256(message)))?)
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Bytes, RelayerError> {
+        let signature = self.wallet.sign_transaction(tx).await?;
+        Ok(tx.rlp_signed(&signature))
+    }
+}
+
+/// Delegates signing to Web3Signer, or to Clef for transactions only
+#[derive(Clone, Debug)]
+pub struct RemoteSigner {
+    url: String,
+    client: Provider<Http>,
+    http: reqwest::Client,
+    address: Address,
+}
+
+/// Clef and geth wrap the signed transaction, Web3Signer returns it bare
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SignTransactionResponse {
+    Raw(Bytes),
+    Envelope { raw: Bytes },
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str, address: Address) -> Result<Self, RelayerError> {
+        let client = Provider::<Http>::try_from(url).map_err(|err| RelayerError::RemoteSignerError(err.to_string()))?;
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            client,
+            http: reqwest::Client::new(),
+            address,
+        })
+    }
+}
+
+#[async_trait]
+impl RelayerSigner for RemoteSigner {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    /// `eth_sign` always adds the EIP-191 prefix, so this uses Web3Signer's eth1 sign endpoint
+    /// which signs the keccak256 of the data as is. Clef has no equivalent.
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, RelayerError> {
+        let url = format!("{}/api/v1/eth1/sign/{:?}", self.url, self.address);
+        let response = self
+            .http
+            .post(url)
+            .json(&serde_json::json!({ "data": Bytes::from(message.to_vec()) }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| RelayerError::RemoteSignerError(err.to_string()))?;
+        let signature = response
+            .text()
+            .await
+            .map_err(|err| RelayerError::RemoteSignerError(err.to_string()))?;
+        let signature = hex::decode(signature.trim().trim_matches('"').trim_start_matches("0x"))
+            .map_err(|err| RelayerError::RemoteSignerError(err.to_string()))?;
+        Signature::try_from(signature.as_slice()).map_err(|err| RelayerError::RemoteSignerError(err.to_string()))
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Bytes, RelayerError> {
+        let mut tx = tx.clone();
+        tx.set_from(self.address);
+        let response: SignTransactionResponse = self.client.request("eth_signTransaction", [tx]).await?;
+        match response {
+            SignTransactionResponse::Raw(raw) | SignTransactionResponse::Envelope { raw } => Ok(raw),
+        }
+    }
+}
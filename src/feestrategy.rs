@@ -0,0 +1,118 @@
+use crate::error::RelayerError;
+use ethers::prelude::Middleware;
+use ethers::providers::{Provider, Ws};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, BlockNumber, Bytes, Eip1559TransactionRequest, TransactionRequest, U256};
+
+const DEFAULT_GAS_MULTIPLIER_PERCENT: u64 = 120;
+/// Replacements get 25% higher fees, above the 10% bump the mempool requires to accept them
+const FEE_BUMP_PERCENT: u64 = 125;
+
+/// How transactions sent to the Thea contract are priced and sized
+#[derive(Clone, Debug)]
+pub struct FeeStrategy {
+    /// Send legacy transactions, for chains without EIP-1559
+    pub legacy: bool,
+    /// Cap on the max fee per gas, or on the gas price of legacy transactions
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// Percentage applied to the gas estimate
+    pub gas_multiplier_percent: u64,
+    /// Nothing is submitted while the base fee, or gas price of legacy transactions, is above it
+    pub gas_price_ceiling: Option<U256>,
+}
+
+impl Default for FeeStrategy {
+    fn default() -> Self {
+        Self {
+            legacy: false,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            gas_multiplier_percent: DEFAULT_GAS_MULTIPLIER_PERCENT,
+            gas_price_ceiling: None,
+        }
+    }
+}
+
+impl FeeStrategy {
+    pub fn transaction(&self, to: Address, data: Bytes) -> TypedTransaction {
+        if self.legacy {
+            TransactionRequest::new().to(to).data(data).into()
+        } else {
+            Eip1559TransactionRequest::new().to(to).data(data).into()
+        }
+    }
+
+    /// Sets the gas limit and fees from the network, refusing when it is above the ceiling and `pause_above_ceiling`
+    pub async fn apply(&self, provider: &Provider<Ws>, tx: &mut TypedTransaction, pause_above_ceiling: bool) -> Result<(), RelayerError> {
+        let gas = provider.estimate_gas(tx, None).await?;
+        tx.set_gas(gas * self.gas_multiplier_percent / 100);
+        if let Some(eip1559_tx) = tx.as_eip1559_mut() {
+            let base_fee = provider
+                .get_block(BlockNumber::Latest)
+                .await?
+                .and_then(|block| block.base_fee_per_gas)
+                .unwrap_or_default();
+            if pause_above_ceiling {
+                self.check_ceiling(base_fee)?;
+            }
+            let (max_fee, priority_fee) = provider.estimate_eip1559_fees(None).await?;
+            let (max_fee, priority_fee) = self.capped_fees(max_fee, priority_fee);
+            eip1559_tx.max_fee_per_gas = Some(max_fee);
+            eip1559_tx.max_priority_fee_per_gas = Some(priority_fee);
+        } else {
+            let gas_price = provider.get_gas_price().await?;
+            if pause_above_ceiling {
+                self.check_ceiling(gas_price)?;
+            }
+            tx.set_gas_price(capped(gas_price, self.max_fee_per_gas));
+        }
+        Ok(())
+    }
+
+    /// EIP-1559 fees within the caps, the priority fee is clamped first so it never lifts the max fee over its cap
+    pub fn capped_fees(&self, max_fee: U256, priority_fee: U256) -> (U256, U256) {
+        let priority_fee = capped(capped(priority_fee, self.max_priority_fee_per_gas), self.max_fee_per_gas);
+        let max_fee = capped(max_fee, self.max_fee_per_gas).max(priority_fee);
+        (max_fee, priority_fee)
+    }
+
+    /// Raises the fees of a replacement, `false` when that would go over the configured caps
+    pub fn bump(&self, tx: &mut TypedTransaction) -> bool {
+        if let Some(eip1559_tx) = tx.as_eip1559_mut() {
+            let max_fee = bumped(eip1559_tx.max_fee_per_gas.unwrap_or_default());
+            let priority_fee = bumped(eip1559_tx.max_priority_fee_per_gas.unwrap_or_default());
+            if exceeds(max_fee, self.max_fee_per_gas) || exceeds(priority_fee, self.max_priority_fee_per_gas) {
+                return false;
+            }
+            eip1559_tx.max_fee_per_gas = Some(max_fee);
+            eip1559_tx.max_priority_fee_per_gas = Some(priority_fee);
+        } else {
+            let gas_price = bumped(tx.gas_price().unwrap_or_default());
+            if exceeds(gas_price, self.max_fee_per_gas) {
+                return false;
+            }
+            tx.set_gas_price(gas_price);
+        }
+        true
+    }
+
+    fn check_ceiling(&self, network_price: U256) -> Result<(), RelayerError> {
+        match self.gas_price_ceiling {
+            Some(ceiling) if network_price > ceiling => Err(RelayerError::GasPriceAboveCeiling(network_price, ceiling)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn capped(value: U256, cap: Option<U256>) -> U256 {
+    cap.map_or(value, |cap| value.min(cap))
+}
+
+fn exceeds(value: U256, cap: Option<U256>) -> bool {
+    cap.map_or(false, |cap| value > cap)
+}
+
+fn bumped(value: U256) -> U256 {
+    value * FEE_BUMP_PERCENT / 100
+}
@@ -0,0 +1,105 @@
+use crate::error::RelayerError;
+use crate::signer::SubstrateSigner;
+use base64::Engine;
+use crypto_secretbox::aead::Aead;
+use crypto_secretbox::{Key, KeyInit, Nonce, XSalsa20Poly1305};
+use ethers::prelude::LocalWallet;
+use ethers::utils::hex;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Environment variables holding secrets when they are not passed as files
+pub const EVM_SEED_ENV: &str = "RELAYER_EVM_SEED";
+pub const EVM_KEYSTORE_PASSWORD_ENV: &str = "RELAYER_EVM_KEYSTORE_PASSWORD";
+pub const SUBSTRATE_KEYSTORE_PASSWORD_ENV: &str = "RELAYER_SUB_KEYSTORE_PASSWORD";
+
+// Layout of the polkadot-js encrypted JSON payload
+const SALT_LENGTH: usize = 32;
+const SCRYPT_PARAMS_LENGTH: usize = SALT_LENGTH + 12;
+const NONCE_LENGTH: usize = 24;
+const PKCS8_HEADER: [u8; 16] = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112, 4, 34, 4, 32];
+const SR25519_SECRET_LENGTH: usize = 64;
+const ECDSA_SECRET_LENGTH: usize = 32;
+
+#[derive(Deserialize)]
+struct PolkadotJsKeystore {
+    encoded: String,
+    encoding: PolkadotJsEncoding,
+}
+
+#[derive(Deserialize)]
+struct PolkadotJsEncoding {
+    content: Vec<String>,
+    #[serde(rename = "type")]
+    kind: Vec<String>,
+}
+
+/// Reads a password from a file, falling back to the given environment variable
+pub fn read_password(password_file: Option<PathBuf>, env_var: &str) -> Result<String, RelayerError> {
+    match password_file {
+        Some(password_file) => Ok(std::fs::read_to_string(password_file)?.trim_end_matches(&['\r', '\n'][..]).to_string()),
+        None => Ok(std::env::var(env_var)?),
+    }
+}
+
+/// Resolves the EVM private key from the CLI seed, a Web3 secret-storage file or the environment
+pub fn load_evm_seed(
+    seed: Option<String>,
+    keystore: Option<PathBuf>,
+    password_file: Option<PathBuf>,
+) -> Result<String, RelayerError> {
+    if let Some(seed) = seed {
+        return Ok(seed);
+    }
+    if let Some(keystore) = keystore {
+        let password = read_password(password_file, EVM_KEYSTORE_PASSWORD_ENV)?;
+        let wallet = LocalWallet::decrypt_keystore(keystore, password)?;
+        return Ok(hex::encode(wallet.signer().to_bytes()));
+    }
+    Ok(std::env::var(EVM_SEED_ENV)?)
+}
+
+/// Decrypts a polkadot-js style encrypted JSON account export
+pub fn decrypt_substrate_keystore(path: PathBuf, password: &str) -> Result<SubstrateSigner, RelayerError> {
+    let keystore: PolkadotJsKeystore = serde_json::from_slice(&std::fs::read(path)?)?;
+    if !keystore.encoding.kind.iter().any(|kind| kind == "scrypt")
+        || !keystore.encoding.kind.iter().any(|kind| kind == "xsalsa20-poly1305")
+    {
+        return Err(RelayerError::KeystoreError("Unsupported encryption type".to_string()));
+    }
+    let encoded = base64::engine::general_purpose::STANDARD
+        .decode(keystore.encoded)
+        .map_err(|err| RelayerError::KeystoreError(err.to_string()))?;
+    if encoded.len() < SCRYPT_PARAMS_LENGTH + NONCE_LENGTH {
+        return Err(RelayerError::KeystoreError("Encrypted payload too short".to_string()));
+    }
+    let (salt, rest) = encoded.split_at(SALT_LENGTH);
+    let read_u32 = |offset: usize| u32::from_le_bytes([rest[offset], rest[offset + 1], rest[offset + 2], rest[offset + 3]]);
+    let (n, p, r) = (read_u32(0), read_u32(4), read_u32(8));
+    let params = scrypt::Params::new(n.trailing_zeros() as u8, r, p)
+        .map_err(|err| RelayerError::KeystoreError(err.to_string()))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|err| RelayerError::KeystoreError(err.to_string()))?;
+    let (nonce, ciphertext) = encoded[SCRYPT_PARAMS_LENGTH..].split_at(NONCE_LENGTH);
+    let decrypted = XSalsa20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| RelayerError::KeystoreError("Invalid password".to_string()))?;
+    if !decrypted.starts_with(&PKCS8_HEADER) {
+        return Err(RelayerError::KeystoreError("Invalid PKCS8 header".to_string()));
+    }
+    let secret = &decrypted[PKCS8_HEADER.len()..];
+    match keystore.encoding.content.get(1).map(String::as_str) {
+        Some("sr25519") if secret.len() >= SR25519_SECRET_LENGTH => {
+            let secret = schnorrkel::SecretKey::from_ed25519_bytes(&secret[..SR25519_SECRET_LENGTH])
+                .map_err(|err| RelayerError::KeystoreError(err.to_string()))?;
+            Ok(SubstrateSigner::Sr25519Secret(secret.to_keypair()))
+        }
+        Some("ecdsa") if secret.len() >= ECDSA_SECRET_LENGTH => {
+            let mut seed = [0u8; ECDSA_SECRET_LENGTH];
+            seed.copy_from_slice(&secret[..ECDSA_SECRET_LENGTH]);
+            Ok(SubstrateSigner::Ecdsa(subxt_signer::ecdsa::Keypair::from_seed(seed)?))
+        }
+        _ => Err(RelayerError::KeystoreError("Unsupported key type".to_string())),
+    }
+}
@@ -3,13 +3,35 @@ use crate::cli::Cli;
 use crate::relayer::RelayerBuilder;
 use structopt::StructOpt;
 use crate::error::RelayerError;
+use crate::checkpoint::CheckpointStore;
+use crate::signer::SubstrateSigner;
+use crate::evmsigner::RemoteSigner;
+use crate::feestrategy::FeeStrategy;
+use crate::confirmation::DepositConfirmation;
+use std::sync::Arc;
+use std::time::Duration;
+use ethers::types::U256;
 
+mod batcher;
 mod builder;
+mod checkpoint;
+mod claimer;
 mod cli;
+mod confirmation;
 mod evmclient;
+mod evmprovider;
+mod evmsigner;
+mod feestrategy;
+mod keystore;
+mod noncemanager;
+mod proof;
 mod relayer;
+mod signer;
 mod substrateclient;
+mod supervisor;
 mod traits;
+mod watchtower;
+mod withdrawal;
 #[cfg(test)]
 mod test;
 pub mod error;
@@ -18,18 +40,70 @@ pub mod error;
 async fn main() -> Result<(), RelayerError> {
     env_logger::init();
     let opt: Cli = cli::Cli::from_args();
-    let evm_client = Builder::default()
+    let evm_builder = Builder::default()
         .chain_url(opt.eth_url)
         .contract_address(opt.thea_contract_address)
-        .seed(opt.evm_seed)
-        .contract(opt.thea_contract)?
+        .network_id(opt.evn_network_id)
+        .chain_id(opt.evm_chain_id)
+        .start_block(opt.evm_start_block)
+        .log_page_size(opt.evm_log_page_size)
+        .tx_confirmations(opt.evm_tx_confirmations)
+        .fee_strategy(FeeStrategy {
+            legacy: opt.evm_legacy_tx,
+            max_fee_per_gas: opt.evm_max_fee_gwei.map(gwei),
+            max_priority_fee_per_gas: opt.evm_max_priority_fee_gwei.map(gwei),
+            gas_multiplier_percent: opt.evm_gas_multiplier_percent,
+            gas_price_ceiling: opt.evm_gas_price_ceiling_gwei.map(gwei),
+        })
+        .deposit_confirmation(if opt.evm_deposit_finalized {
+            DepositConfirmation::Finalized
+        } else {
+            DepositConfirmation::Blocks(opt.evm_deposit_confirmations)
+        })
+        .contract(opt.thea_contract)?;
+    let evm_client = match opt.evm_remote_signer_url {
+        Some(url) => {
+            let address = opt.evm_remote_signer_address.ok_or_else(|| {
+                RelayerError::RemoteSignerError("--evm-remote-signer-address is required".to_string())
+            })?;
+            let signer = RemoteSigner::new(&url, address)?;
+            evm_builder.evm_signer(Arc::new(signer)).build().await?
+        }
+        None => {
+            let evm_seed = keystore::load_evm_seed(opt.evm_seed, opt.evm_keystore, opt.evm_keystore_password_file)?;
+            evm_builder.seed(evm_seed).build().await?
+        }
+    };
+    let substrate_signer = SubstrateSigner::load(
+        opt.sub_key_type,
+        opt.sub_phase,
+        opt.sub_key_file,
+        opt.sub_keystore,
+        opt.sub_keystore_password_file,
+    )?;
+    let substrate_client = Builder::default()
+        .chain_url(opt.sub_url)
+        .substrate_signer(substrate_signer)
+        .network_id(opt.substrate_network_id)
         .build()
         .await?;
-    let substrate_client = Builder::default().chain_url(opt.sub_url).build().await?;
+    let checkpoint = CheckpointStore::open(opt.db_path)?;
     let mut relayer = RelayerBuilder::default()
         .evm_client(evm_client)
         .substrate_client(substrate_client)
+        .checkpoint(checkpoint)
+        .deposit_batch_blocks(opt.deposit_batch_blocks)
+        .claim_delay(
+            opt.withdrawal_claim_delay_secs
+                .filter(|_| opt.claim_withdrawals)
+                .map(Duration::from_secs),
+        )
+        .watchtower(opt.watchtower)
         .build();
     relayer.run().await?;
     Ok(())
 }
+
+fn gwei(amount: u64) -> U256 {
+    U256::from(amount) * U256::exp10(9)
+}
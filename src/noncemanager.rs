@@ -0,0 +1,270 @@
+use crate::checkpoint::{CheckpointStore, ExtrinsicOutcome, ExtrinsicStatus, PendingIncoming};
+use crate::error::RelayerError;
+use crate::substrateclient::{SubstrateClient, MORTAL_PERIOD};
+use crate::traits::TheaMessage;
+use futures::StreamExt;
+use parity_scale_codec::{Decode, Encode};
+use std::collections::HashSet;
+use std::sync::Arc;
+use subxt::tx::TxProgress;
+use subxt::utils::{AccountId32, H256};
+use subxt::{OnlineClient, SubstrateConfig};
+use thea_primitives::types::Deposit;
+use tokio::sync::Mutex;
+
+type IncomingMessageProgress = TxProgress<SubstrateConfig, OnlineClient<SubstrateConfig>>;
+
+/// Hands out Thea incoming nonces ahead of finalization and resubmits messages whose extrinsic was dropped
+#[derive(Clone, Debug)]
+pub struct IncomingNonceManager {
+    network_id: u8,
+    checkpoint: CheckpointStore,
+    // Held from reserving a nonce until the message is recorded as pending
+    lock: Arc<Mutex<()>>,
+}
+
+impl IncomingNonceManager {
+    pub fn new(network_id: u8, checkpoint: CheckpointStore) -> Self {
+        Self {
+            network_id,
+            checkpoint,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Submits the deposits with the next free nonce, following the extrinsic
+    /// to finality in the background so the caller can move on to the next message
+    pub async fn submit(
+        &self,
+        client: &SubstrateClient,
+        data: Vec<u8>,
+        block_no: u64,
+        messages: Vec<TheaMessage>,
+    ) -> Result<(), RelayerError> {
+        let Some((progress, submitted)) = self.submit_pending(client, data, block_no).await? else {
+            return Ok(());
+        };
+        self.spawn_follow(progress, submitted, messages);
+        Ok(())
+    }
+
+    fn spawn_follow(&self, progress: IncomingMessageProgress, submitted: PendingIncoming, messages: Vec<TheaMessage>) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let nonce = submitted.nonce;
+            if let Err(err) = manager.follow(progress, submitted, messages).await {
+                println!("Failed to follow incoming message {:?}: {:?}", nonce, err);
+            }
+        });
+    }
+
+    /// Reserves the next nonce and records the message as pending, `None` when every deposit is already pending
+    /// or the message is queued behind one that has to be resubmitted first
+    async fn submit_pending(
+        &self,
+        client: &SubstrateClient,
+        data: Vec<u8>,
+        block_no: u64,
+    ) -> Result<Option<(IncomingMessageProgress, PendingIncoming)>, RelayerError> {
+        let _guard = self.lock.lock().await;
+        let finalized_nonce = client.incoming_nonce(None).await?;
+        let pending = self.prune(finalized_nonce)?;
+        // Deposits replayed after a restart may still be pending, those are left to reconciliation
+        let Some(data) = Self::unsubmitted_deposits(&pending, data)? else {
+            println!("Deposits already pending in an incoming message, not resubmitting them");
+            return Ok(None);
+        };
+        let nonce = Self::next_nonce(finalized_nonce, &pending);
+        let mut message = PendingIncoming {
+            nonce,
+            block_no,
+            data,
+            submitted_at: None,
+        };
+        // Dispatch would fail until the earlier message lands, reconciliation submits both in order
+        if pending.iter().any(|pending| pending.nonce < nonce && pending.submitted_at.is_none()) {
+            println!("Queueing incoming message {:?} behind one waiting to be resubmitted", nonce);
+            self.checkpoint.add_pending_incoming(self.network_id, &message)?;
+            return Ok(None);
+        }
+        let (progress, submitted_at) = client.submit_incoming_message(nonce, message.data.clone(), block_no).await?;
+        message.submitted_at = Some(submitted_at);
+        self.checkpoint.add_pending_incoming(self.network_id, &message)?;
+        Ok(Some((progress, message)))
+    }
+
+    /// Lowest nonce after the finalized one that no pending message holds, so a gap left by a failed
+    /// message is filled before anything is sent past it
+    pub fn next_nonce(finalized_nonce: u64, pending: &[PendingIncoming]) -> u64 {
+        (finalized_nonce.saturating_add(1)..)
+            .find(|nonce| pending.iter().all(|pending| pending.nonce != *nonce))
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Tracks the message until it is finalized and records how its dispatch failed
+    async fn follow(
+        &self,
+        progress: IncomingMessageProgress,
+        submitted: PendingIncoming,
+        messages: Vec<TheaMessage>,
+    ) -> Result<(), RelayerError> {
+        match self.track(progress, submitted.nonce).await {
+            Err(err) if err.is_fatal() => Err(err),
+            Err(err) => self.record_failure(&submitted, &err, &messages).await,
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// When the previous nonce had not landed yet the message stays pending for reconciliation to resubmit,
+    /// any other dispatch failure moves its deposits to dead letters. Nothing changes if the message was
+    /// resubmitted in the meantime.
+    pub async fn record_failure(
+        &self,
+        submitted: &PendingIncoming,
+        err: &RelayerError,
+        messages: &[TheaMessage],
+    ) -> Result<(), RelayerError> {
+        let _guard = self.lock.lock().await;
+        let pending = self.checkpoint.pending_incoming(self.network_id)?;
+        if !pending.contains(submitted) {
+            return Ok(());
+        }
+        if err.is_nonce_mismatch() {
+            println!("Incoming message {:?} failed: {:?}, leaving it to reconciliation", submitted.nonce, err);
+            return self.checkpoint.add_pending_incoming(
+                self.network_id,
+                &PendingIncoming {
+                    submitted_at: None,
+                    ..submitted.clone()
+                },
+            );
+        }
+        // Resubmitted by reconciliation, the original messages are not known so the deposits stay pending
+        // until the extrinsic expires
+        if messages.is_empty() {
+            println!("Incoming message {:?} failed: {:?}", submitted.nonce, err);
+            return Ok(());
+        }
+        // A failed dispatch never executes, so the deposits are free to go into another message
+        self.checkpoint.remove_pending_incoming(self.network_id, submitted.nonce)?;
+        for message in messages {
+            println!("Moving {:?} to dead letters: {:?}", message, err);
+            self.checkpoint.add_dead_letter(message, err, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the extrinsic carrying `nonce` and records how it ended, only dispatch failures are returned
+    async fn track(&self, progress: IncomingMessageProgress, nonce: u64) -> Result<(), RelayerError> {
+        let extrinsic_hash = progress.extrinsic_hash();
+        let (status, result) = match SubstrateClient::wait_for_finalized(progress).await {
+            Ok(block_hash) => (ExtrinsicStatus::Finalized(block_hash.0), Ok(())),
+            Err(err @ (RelayerError::TheaError(_) | RelayerError::ExtrinsicFailed(_))) => {
+                (ExtrinsicStatus::Failed(err.to_string()), Err(err))
+            }
+            // Still pending, reconciliation resubmits it if it never gets finalized
+            Err(err) => (ExtrinsicStatus::Dropped(err.to_string()), Ok(())),
+        };
+        println!("Incoming message {:?} extrinsic {:?}: {:?}", nonce, extrinsic_hash, status);
+        self.checkpoint.add_extrinsic_outcome(&ExtrinsicOutcome {
+            network_id: self.network_id,
+            nonce,
+            extrinsic_hash: extrinsic_hash.0,
+            status,
+        })?;
+        result
+    }
+
+    /// Reconciles pending messages with every finalized block
+    pub async fn reconcile_finalized(&self, client: &SubstrateClient) -> Result<(), RelayerError> {
+        let mut blocks = client.subscribe_finalized_blocks().await?;
+        while let Some(block) = blocks.next().await {
+            let (number, hash) = block?;
+            self.reconcile(client, number, hash).await?;
+        }
+        Ok(())
+    }
+
+    /// Forgets finalized messages and resubmits the ones no extrinsic can include any more. Messages whose
+    /// extrinsic may still land keep their nonce, renumbering those could credit their deposits twice
+    pub async fn reconcile(&self, client: &SubstrateClient, finalized_number: u64, finalized_hash: H256) -> Result<(), RelayerError> {
+        let _guard = self.lock.lock().await;
+        let finalized_nonce = client.incoming_nonce(Some(finalized_hash)).await?;
+        let pending = self.prune(finalized_nonce)?;
+        for (nonce, message) in Self::resubmissions(finalized_nonce, finalized_number, &pending) {
+            println!("Incoming message {:?} can no longer be included, resubmitting it with nonce {:?}", message.nonce, nonce);
+            // On failure the message is still recorded under its old nonce and the next block retries it
+            let (progress, submitted_at) = client.submit_incoming_message(nonce, message.data.clone(), message.block_no).await?;
+            let resubmitted = PendingIncoming {
+                nonce,
+                submitted_at: Some(submitted_at),
+                ..message.clone()
+            };
+            self.replace_pending(&message, &resubmitted)?;
+            self.spawn_follow(progress, resubmitted, vec![]);
+        }
+        Ok(())
+    }
+
+    /// Expired messages along with the nonce to resubmit each with. They take the lowest nonces after the
+    /// finalized one and never go past a message that may still be included, if that one is dropped they
+    /// would only fail again
+    pub fn resubmissions(finalized_nonce: u64, finalized_number: u64, pending: &[PendingIncoming]) -> Vec<(u64, PendingIncoming)> {
+        let first_live = pending
+            .iter()
+            .find(|pending| !pending.is_expired(finalized_number))
+            .map_or(u64::MAX, |pending| pending.nonce);
+        (finalized_nonce.saturating_add(1)..first_live)
+            .zip(pending.iter().filter(|pending| pending.is_expired(finalized_number)).cloned())
+            .collect()
+    }
+
+    /// Records the resubmitted message before forgetting the old one, so its deposits are always stored
+    pub fn replace_pending(&self, old: &PendingIncoming, new: &PendingIncoming) -> Result<(), RelayerError> {
+        self.checkpoint.add_pending_incoming(self.network_id, new)?;
+        if old.nonce != new.nonce {
+            self.checkpoint.remove_pending_incoming(self.network_id, old.nonce)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the deposits already carried by a pending message, `None` when nothing is left to submit
+    pub fn unsubmitted_deposits(pending: &[PendingIncoming], data: Vec<u8>) -> Result<Option<Vec<u8>>, RelayerError> {
+        let mut submitted = HashSet::new();
+        for pending in pending {
+            let deposits: Vec<Deposit<AccountId32>> = Decode::decode(&mut &pending.data[..])?;
+            submitted.extend(deposits.iter().map(Encode::encode));
+        }
+        let deposits: Vec<Deposit<AccountId32>> = Decode::decode(&mut &data[..])?;
+        let count = deposits.len();
+        let deposits: Vec<Deposit<AccountId32>> = deposits
+            .into_iter()
+            .filter(|deposit| !submitted.contains(&deposit.encode()))
+            .collect();
+        if deposits.is_empty() {
+            return Ok(None);
+        }
+        if deposits.len() == count {
+            return Ok(Some(data));
+        }
+        Ok(Some(deposits.encode()))
+    }
+
+    // Drops pending messages at or below the finalized nonce and returns the rest
+    fn prune(&self, finalized_nonce: u64) -> Result<Vec<PendingIncoming>, RelayerError> {
+        let mut pending = self.checkpoint.pending_incoming(self.network_id)?;
+        for finalized in pending.iter().filter(|pending| pending.nonce <= finalized_nonce) {
+            self.checkpoint.remove_pending_incoming(self.network_id, finalized.nonce)?;
+        }
+        pending.retain(|pending| pending.nonce > finalized_nonce);
+        Ok(pending)
+    }
+}
+
+impl PendingIncoming {
+    /// The extrinsic is mortal, past its period it can no longer be included
+    pub fn is_expired(&self, finalized_number: u64) -> bool {
+        self.submitted_at
+            .map_or(true, |submitted_at| finalized_number > submitted_at.saturating_add(MORTAL_PERIOD))
+    }
+}
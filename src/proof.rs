@@ -0,0 +1,44 @@
+use crate::error::RelayerError;
+use ethers::types::{Address, H256};
+use sp_core::ecdsa::Signature;
+use sp_core::hashing::keccak_256;
+
+/// Checks the proof the way `sendMessage` will: the signature at every index returned by `getValidatorIndex`
+/// has to recover to the validator address the contract holds at that index, and there have to be at least
+/// `index_size` of them
+pub fn verify_signatures(
+    message: &[u8],
+    validators: &[(u64, Address)],
+    index_size: usize,
+    signatures: &[(u32, Signature)],
+) -> Result<(), RelayerError> {
+    let message_hash = H256(keccak_256(message));
+    let mut valid = 0;
+    for (index, validator) in validators {
+        let Some((_, signature)) = signatures.iter().find(|(signature_index, _)| u64::from(*signature_index) == *index) else {
+            println!("No signature from validator {:?}", index);
+            continue;
+        };
+        let recovered = ethers::types::Signature::try_from(&signature.0[..])
+            .ok()
+            .and_then(|signature| signature.recover(message_hash).ok());
+        if recovered == Some(*validator) {
+            valid += 1;
+        } else {
+            println!("Invalid signature from validator {:?}", index);
+        }
+    }
+    let required = index_size.max(validators.len());
+    if valid < required {
+        return Err(RelayerError::NotEnoughSignatures(valid, required));
+    }
+    Ok(())
+}
+
+/// The contract only accepts proofs from validator sets it was told about, either active or scheduled
+pub fn check_validator_set(validator_set_id: u64, known_validator_set_id: u64) -> Result<(), RelayerError> {
+    if validator_set_id > known_validator_set_id {
+        return Err(RelayerError::UnknownValidatorSet(validator_set_id, known_validator_set_id));
+    }
+    Ok(())
+}
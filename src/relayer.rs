@@ -5,123 +5,404 @@ use actix_web::{error, middleware, web, App, HttpResponse, HttpServer, Responder
 use parity_scale_codec::{Decode, Encode};
 use sp_application_crypto::RuntimePublic;
 use std::sync::Arc;
+use std::time::Duration;
 use thea_primitives::types::ApprovedMessage;
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use crate::error::RelayerError;
+use crate::checkpoint::CheckpointStore;
+use crate::supervisor::{shutdown_signal, Supervisor};
+use crate::batcher::DepositBatcher;
+use crate::claimer::WithdrawalClaimer;
+use crate::watchtower::Watchtower;
+use crate::noncemanager::IncomingNonceManager;
+use crate::withdrawal::WithdrawalRelay;
+use tokio::time::Instant;
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// How often the validator set known to the contract is compared with Polkadex
+const VALIDATOR_SET_POLL_INTERVAL: Duration = Duration::from_secs(12);
 
 pub struct NoEvmClient;
 pub struct EvmClientA(EvmClient);
 pub struct NoSubstrateClient;
 pub struct SubstrateClientA(SubstrateClient);
+pub struct NoCheckpointStore;
+pub struct CheckpointStoreA(CheckpointStore);
 
-pub struct RelayerBuilder<EvmClientX, SubstrateClientX> {
+pub struct RelayerBuilder<EvmClientX, SubstrateClientX, CheckpointStoreX> {
     evm_client: EvmClientX,
-    substrate_client: SubstrateClientX
+    substrate_client: SubstrateClientX,
+    checkpoint: CheckpointStoreX,
+    deposit_batch_blocks: u64,
+    claim_delay: Option<Duration>,
+    watchtower: bool
 }
 
-impl Default for RelayerBuilder<NoEvmClient, NoSubstrateClient> {
+const DEFAULT_DEPOSIT_BATCH_BLOCKS: u64 = 1;
+
+impl Default for RelayerBuilder<NoEvmClient, NoSubstrateClient, NoCheckpointStore> {
     fn default() -> Self {
         RelayerBuilder {
             evm_client: NoEvmClient,
             substrate_client: NoSubstrateClient,
+            checkpoint: NoCheckpointStore,
+            deposit_batch_blocks: DEFAULT_DEPOSIT_BATCH_BLOCKS,
+            claim_delay: None,
+            watchtower: false,
         }
     }
 }
 
-impl<EvmClientX, SubstrateClientX>
-    RelayerBuilder<EvmClientX, SubstrateClientX>
+impl<EvmClientX, SubstrateClientX, CheckpointStoreX>
+    RelayerBuilder<EvmClientX, SubstrateClientX, CheckpointStoreX>
 {
     pub fn evm_client(
         self,
         evm_client: EvmClient,
-    ) -> RelayerBuilder<EvmClientA, SubstrateClientX> {
+    ) -> RelayerBuilder<EvmClientA, SubstrateClientX, CheckpointStoreX> {
         RelayerBuilder {
             evm_client: EvmClientA(evm_client),
             substrate_client: self.substrate_client,
+            checkpoint: self.checkpoint,
+            deposit_batch_blocks: self.deposit_batch_blocks,
+            claim_delay: self.claim_delay,
+            watchtower: self.watchtower,
         }
     }
 
     pub fn substrate_client(
         self,
         substrate_client: SubstrateClient,
-    ) -> RelayerBuilder<EvmClientX, SubstrateClientA> {
+    ) -> RelayerBuilder<EvmClientX, SubstrateClientA, CheckpointStoreX> {
         RelayerBuilder {
             evm_client: self.evm_client,
-            substrate_client: SubstrateClientA(substrate_client)
+            substrate_client: SubstrateClientA(substrate_client),
+            checkpoint: self.checkpoint,
+            deposit_batch_blocks: self.deposit_batch_blocks,
+            claim_delay: self.claim_delay,
+            watchtower: self.watchtower,
+        }
+    }
+
+    pub fn checkpoint(
+        self,
+        checkpoint: CheckpointStore,
+    ) -> RelayerBuilder<EvmClientX, SubstrateClientX, CheckpointStoreA> {
+        RelayerBuilder {
+            evm_client: self.evm_client,
+            substrate_client: self.substrate_client,
+            checkpoint: CheckpointStoreA(checkpoint),
+            deposit_batch_blocks: self.deposit_batch_blocks,
+            claim_delay: self.claim_delay,
+            watchtower: self.watchtower,
         }
     }
+
+    /// Number of consecutive EVM blocks whose deposits are submitted in one incoming message
+    pub fn deposit_batch_blocks(mut self, deposit_batch_blocks: u64) -> Self {
+        self.deposit_batch_blocks = deposit_batch_blocks;
+        self
+    }
+
+    /// Claims withdrawals of processed messages once the delay passed, disabled when `None`
+    pub fn claim_delay(mut self, claim_delay: Option<Duration>) -> Self {
+        self.claim_delay = claim_delay;
+        self
+    }
+
+    /// Blocks pending EVM withdrawals that do not match the messages signed on Polkadex
+    pub fn watchtower(mut self, watchtower: bool) -> Self {
+        self.watchtower = watchtower;
+        self
+    }
 }
 
-impl RelayerBuilder<EvmClientA, SubstrateClientA> {
+impl RelayerBuilder<EvmClientA, SubstrateClientA, CheckpointStoreA> {
     pub fn build(self) -> Relayer {
+        let nonce_manager = IncomingNonceManager::new(self.substrate_client.0.network_id(), self.checkpoint.0.clone());
         Relayer {
             evm_client: self.evm_client.0,
-            substrate_client: self.substrate_client.0
+            substrate_client: self.substrate_client.0,
+            checkpoint: self.checkpoint.0,
+            deposit_batch_blocks: self.deposit_batch_blocks,
+            claim_delay: self.claim_delay,
+            watchtower: self.watchtower,
+            nonce_manager
         }
     }
 }
 
 pub struct Relayer {
     evm_client: EvmClient,
-    substrate_client: SubstrateClient
+    substrate_client: SubstrateClient,
+    checkpoint: CheckpointStore,
+    deposit_batch_blocks: u64,
+    claim_delay: Option<Duration>,
+    watchtower: bool,
+    nonce_manager: IncomingNonceManager
 }
 
 impl Relayer {
     pub async fn run(&mut self) -> Result<(), RelayerError> {
         let mut evm_deposit_channel = Channel::<TheaMessage>::new();
-        // spawn following tasks
-        let evm_client = self.evm_client.clone();
-        let sender = evm_deposit_channel.sender().clone();
+        // Replay messages that were picked up but not submitted before the last shutdown
+        for message in self.checkpoint.in_flight()? {
+            println!("Replaying in-flight message {:?}", message);
+            evm_deposit_channel.sender().send(message)?;
+        }
+        let mut connection_state = self.evm_client.connection_state();
         tokio::spawn(async move {
-            if let Err(err) = evm_client
-                .subscribe_deposit_events_stream(sender)
-                .await {
-                panic!("Eth Deposit Event Subscription failed {}", err);
+            while connection_state.changed().await.is_ok() {
+                println!("EVM connection state {:?}", *connection_state.borrow());
             }
         });
+        // Without a checkpoint, resume from the last nonce the contract has processed
+        let default_outgoing_nonce = self.evm_client.get_incoming_nonce().await?;
+        let (known_validator_set_sender, known_validator_set) =
+            watch::channel(self.evm_client.known_validator_set_id().await?);
+        let known_validator_set_sender = Arc::new(known_validator_set_sender);
+        let mut supervisor = Supervisor::new();
         let evm_client = self.evm_client.clone();
-        let sender = evm_deposit_channel.sender().clone();
-        tokio::spawn(async move {
-            if let Err(err) = evm_client
-                .subscribe_ob_deposit_events_stream(sender)
-                .await {
-                panic!("Eth OB Deposit event Subscription Failed {}", err);
+        let sender = evm_deposit_channel.sender();
+        let checkpoint = self.checkpoint.clone();
+        supervisor.spawn("Eth Deposit Event Subscription", move || {
+            let evm_client = evm_client.clone();
+            let sender = sender.clone();
+            let checkpoint = checkpoint.clone();
+            async move {
+                evm_client
+                    .subscribe_deposit_events_stream(sender, checkpoint)
+                    .await
             }
         });
-        let substrate_client = self.substrate_client.clone();
-        let sender = evm_deposit_channel.sender().clone();
-        tokio::spawn(async move {
-            if let Err(err) = substrate_client
-                .subscribe_substrate_event_stream(sender)
-                .await {
-                panic!("Substrate Event Subscription Failed {}", err);
+        let evm_client = self.evm_client.clone();
+        let sender = evm_deposit_channel.sender();
+        let checkpoint = self.checkpoint.clone();
+        supervisor.spawn("Eth OB Deposit Event Subscription", move || {
+            let evm_client = evm_client.clone();
+            let sender = sender.clone();
+            let checkpoint = checkpoint.clone();
+            async move {
+                evm_client
+                    .subscribe_ob_deposit_events_stream(sender, checkpoint)
+                    .await
             }
         });
+        let withdrawal_channel = Channel::<TheaMessage>::new();
+        let withdrawal_sender = withdrawal_channel.sender();
+        let withdrawal_relay = WithdrawalRelay::new(
+            self.evm_client.clone(),
+            self.checkpoint.clone(),
+            withdrawal_channel.receiver,
+            known_validator_set.clone(),
+        );
+        supervisor.spawn("Withdrawal Relay", move || {
+            let withdrawal_relay = withdrawal_relay.clone();
+            async move { withdrawal_relay.run().await }
+        });
         let substrate_client = self.substrate_client.clone();
+        let sender = evm_deposit_channel.sender();
+        let checkpoint = self.checkpoint.clone();
+        supervisor.spawn("Substrate Event Subscription", move || {
+            let substrate_client = substrate_client.clone();
+            let sender = sender.clone();
+            let checkpoint = checkpoint.clone();
+            let known_validator_set = known_validator_set.clone();
+            async move {
+                substrate_client
+                    .subscribe_substrate_event_stream(sender, checkpoint, default_outgoing_nonce, known_validator_set)
+                    .await
+            }
+        });
         let evm_client = self.evm_client.clone();
+        let substrate_client = self.substrate_client.clone();
+        supervisor.spawn("Validator Set Tracker", move || {
+            let evm_client = evm_client.clone();
+            let substrate_client = substrate_client.clone();
+            let known_validator_set_sender = known_validator_set_sender.clone();
+            async move { track_validator_sets(&evm_client, &substrate_client, &known_validator_set_sender).await }
+        });
+        let substrate_client = self.substrate_client.clone();
+        let nonce_manager = self.nonce_manager.clone();
+        supervisor.spawn("Incoming Nonce Reconciliation", move || {
+            let substrate_client = substrate_client.clone();
+            let nonce_manager = nonce_manager.clone();
+            async move { nonce_manager.reconcile_finalized(&substrate_client).await }
+        });
+        if let Some(claim_delay) = self.claim_delay {
+            let claimer = WithdrawalClaimer::new(
+                self.evm_client.clone(),
+                self.substrate_client.clone(),
+                self.checkpoint.clone(),
+                claim_delay,
+            );
+            supervisor.spawn("Withdrawal Claimer", move || {
+                let claimer = claimer.clone();
+                async move { claimer.run().await }
+            });
+        }
+        if self.watchtower {
+            let watchtower = Watchtower::new(self.evm_client.clone(), self.substrate_client.clone(), self.checkpoint.clone());
+            supervisor.spawn("Watchtower", move || {
+                let watchtower = watchtower.clone();
+                async move { watchtower.run().await }
+            });
+        }
+        let mut batcher = DepositBatcher::new(self.deposit_batch_blocks);
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
+        let result = loop {
+            tokio::select! {
+                Some(message) = evm_deposit_channel.receiver.recv() => {
+                    if let Err(err) = self.batch_message(&mut batcher, &withdrawal_sender, message).await {
+                        break Err(err);
+                    }
+                }
+                _ = tokio::time::sleep_until(batcher.deadline().unwrap_or_else(Instant::now)), if batcher.deadline().is_some() => {
+                    if let Err(err) = self.relay(batcher.take()).await {
+                        break Err(err);
+                    }
+                }
+                (name, err) = supervisor.fatal_error() => {
+                    println!("{} failed fatally, stopping relayer", name);
+                    break Err(err);
+                }
+                result = &mut shutdown => {
+                    println!("Shutdown requested, draining in-flight messages");
+                    break result;
+                }
+            }
+        };
+        // Stop producing new messages, then submit the deposits already picked up. The withdrawal relay is
+        // gone by now, withdrawals stay in flight and are replayed on the next start.
+        supervisor.shutdown().await;
+        result?;
+        while let Ok(message) = evm_deposit_channel.receiver.try_recv() {
+            if DepositBatcher::block_number(&message).is_none() {
+                continue;
+            }
+            self.batch_message(&mut batcher, &withdrawal_sender, message).await?;
+        }
+        let batch = batcher.take();
+        if !batch.is_empty() {
+            self.relay(batch).await?;
+        }
+        Ok(())
+    }
 
-            loop {
-                if let Some(message) = evm_deposit_channel.receiver.recv().await {
-                    match message {
-                        TheaMessage::EvmDeposit(deposit) => {
-                            substrate_client.handle_deposit(deposit).await?;
-                        }
-                        TheaMessage::ObEvmDeposit(deposit) => {
-                            substrate_client.handle_ob_deposit(deposit).await?;
-                        }
-                        TheaMessage::SubstrateMessage(message) => {
-                            evm_client.handle_substrate_message(message).await?;
-                        }
-                        TheaMessage::SubstrateMessageWithProof(message,validator_set_id, signature) => {
-                            evm_client
-                                .handle_substrate_message_with_proof(message, validator_set_id, signature)
-                                .await?;
-                        }
+    /// Holds deposits back for batching and hands withdrawals over to the withdrawal relay
+    async fn batch_message(
+        &self,
+        batcher: &mut DepositBatcher,
+        withdrawal_sender: &UnboundedSender<TheaMessage>,
+        message: TheaMessage,
+    ) -> Result<(), RelayerError> {
+        match DepositBatcher::block_number(&message) {
+            Some(block_number) => {
+                if let Some(batch) = batcher.push(message, block_number) {
+                    self.relay(batch).await?;
+                }
+                Ok(())
+            }
+            None => Ok(withdrawal_sender.send(message)?),
+        }
+    }
+
+    /// Submits the messages together, falling back to one by one when the batch keeps failing
+    async fn relay(&self, messages: Vec<TheaMessage>) -> Result<(), RelayerError> {
+        if let Some((err, attempts)) = self.submit_with_retry(messages.clone()).await? {
+            if messages.len() > 1 {
+                println!("Batch of {:?} messages failed: {:?}, relaying them one by one", messages.len(), err);
+                for message in &messages {
+                    if let Some((err, attempts)) = self.submit_with_retry(vec![message.clone()]).await? {
+                        self.dead_letter(message, &err, attempts)?;
                     }
                 }
+            } else {
+                for message in &messages {
+                    self.dead_letter(message, &err, attempts)?;
+                }
+            }
+        }
+        for message in &messages {
+            self.checkpoint.remove_in_flight(CheckpointStore::in_flight_key(message)?)?;
+        }
+        Ok(())
+    }
+
+    /// Retries transient failures, returning the last error once the messages are not worth retrying
+    async fn submit_with_retry(&self, messages: Vec<TheaMessage>) -> Result<Option<(RelayerError, u32)>, RelayerError> {
+        let mut backoff = INITIAL_RETRY_BACKOFF;
+        let mut attempts = 1;
+        loop {
+            match self.handle_messages(messages.clone()).await {
+                Ok(()) => return Ok(None),
+                Err(err) if err.is_fatal() => return Err(err),
+                Err(err) if err.is_retryable() && attempts < MAX_ATTEMPTS => {
+                    println!("Failed to relay {:?}: {:?}, retrying in {:?}", messages, err, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempts += 1;
+                }
+                Err(err) => return Ok(Some((err, attempts))),
             }
+        }
+    }
+
+    fn dead_letter(&self, message: &TheaMessage, err: &RelayerError, attempts: u32) -> Result<(), RelayerError> {
+        println!("Moving {:?} to dead letters after {:?} attempts: {:?}", message, attempts, err);
+        self.checkpoint.add_dead_letter(message, err, attempts)
+    }
+
+    /// Deposits are submitted as a single incoming message carrying the latest EVM block number
+    async fn handle_messages(&self, messages: Vec<TheaMessage>) -> Result<(), RelayerError> {
+        let mut deposits = vec![];
+        let mut block_no = 0;
+        for message in messages.clone() {
+            match message {
+                TheaMessage::EvmDeposit(deposit) => {
+                    block_no = block_no.max(deposit.block_number);
+                    deposits.push(SubstrateClient::evm_deposit(deposit)?);
+                }
+                TheaMessage::ObEvmDeposit(deposit) => {
+                    block_no = block_no.max(deposit.block_number);
+                    deposits.push(SubstrateClient::orderbook_deposit(deposit)?);
+                }
+                // Relayed in nonce order by the withdrawal relay
+                TheaMessage::SubstrateMessage(_) | TheaMessage::SubstrateMessageWithProof(..) => {}
+            }
+        }
+        if !deposits.is_empty() {
+            self.nonce_manager
+                .submit(&self.substrate_client, deposits.encode(), block_no, messages)
+                .await?;
+        }
         Ok(())
     }
 }
 
+/// Publishes the validator set known to the contract and reports when Polkadex has rotated past it
+async fn track_validator_sets(
+    evm_client: &EvmClient,
+    substrate_client: &SubstrateClient,
+    known_validator_set_sender: &watch::Sender<u64>,
+) -> Result<(), RelayerError> {
+    let mut interval = tokio::time::interval(VALIDATOR_SET_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let known_validator_set_id = evm_client.known_validator_set_id().await?;
+        let previous = known_validator_set_sender.send_replace(known_validator_set_id);
+        if previous != known_validator_set_id {
+            println!("Contract validator set advanced from {:?} to {:?}", previous, known_validator_set_id);
+        }
+        let validator_set_id = substrate_client.validator_set_id().await?;
+        if validator_set_id > known_validator_set_id {
+            println!(
+                "Polkadex validator set {:?} is ahead of the contract ({:?}), waiting for the rotation to be relayed",
+                validator_set_id, known_validator_set_id
+            );
+        }
+    }
+}
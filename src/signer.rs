@@ -0,0 +1,113 @@
+use crate::error::RelayerError;
+use crate::keystore::{decrypt_substrate_keystore, read_password, SUBSTRATE_KEYSTORE_PASSWORD_ENV};
+use std::path::PathBuf;
+use std::str::FromStr;
+use subxt::config::SubstrateConfig;
+use subxt::tx::Signer;
+use subxt::utils::{AccountId32, MultiAddress, MultiSignature};
+use subxt_signer::{ecdsa, sr25519, SecretUri};
+
+/// Environment variable read for the Substrate secret when no other source is given
+pub const SUBSTRATE_PHRASE_ENV: &str = "RELAYER_SUB_PHRASE";
+
+// Signing context used by Substrate for sr25519
+const SIGNING_CONTEXT: &[u8] = b"substrate";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyType {
+    Sr25519,
+    Ecdsa,
+}
+
+impl FromStr for KeyType {
+    type Err = RelayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sr25519" => Ok(KeyType::Sr25519),
+            "ecdsa" => Ok(KeyType::Ecdsa),
+            _ => Err(RelayerError::InvalidKeyType(s.to_string())),
+        }
+    }
+}
+
+/// Key used to sign every extrinsic the relayer submits to Polkadex
+#[derive(Clone, Debug)]
+pub enum SubstrateSigner {
+    Sr25519(sr25519::Keypair),
+    Ecdsa(ecdsa::Keypair),
+    /// Expanded sr25519 key, as stored in polkadot-js exports
+    Sr25519Secret(schnorrkel::Keypair),
+}
+
+impl SubstrateSigner {
+    /// Accepts anything `SecretUri` does: a mnemonic, a `0x` hex seed and optional derivation paths
+    pub fn from_uri(key_type: KeyType, uri: &str) -> Result<Self, RelayerError> {
+        let uri = SecretUri::from_str(uri.trim())?;
+        match key_type {
+            KeyType::Sr25519 => Ok(SubstrateSigner::Sr25519(sr25519::Keypair::from_uri(&uri)?)),
+            KeyType::Ecdsa => Ok(SubstrateSigner::Ecdsa(ecdsa::Keypair::from_uri(&uri)?)),
+        }
+    }
+
+    /// Reads a secret uri from a file, either raw or as a JSON string like Substrate keystore entries
+    pub fn from_file(key_type: KeyType, path: PathBuf) -> Result<Self, RelayerError> {
+        let contents = std::fs::read_to_string(path)?;
+        let contents = contents.trim();
+        let uri = match serde_json::from_str::<String>(contents) {
+            Ok(uri) => uri,
+            Err(_) => contents.to_string(),
+        };
+        Self::from_uri(key_type, &uri)
+    }
+
+    /// Picks the secret from the phrase, the key file, the encrypted keystore, then the environment
+    pub fn load(
+        key_type: KeyType,
+        phrase: Option<String>,
+        key_file: Option<PathBuf>,
+        keystore: Option<PathBuf>,
+        password_file: Option<PathBuf>,
+    ) -> Result<Self, RelayerError> {
+        if let Some(phrase) = phrase {
+            return Self::from_uri(key_type, &phrase);
+        }
+        if let Some(key_file) = key_file {
+            return Self::from_file(key_type, key_file);
+        }
+        if let Some(keystore) = keystore {
+            let password = read_password(password_file, SUBSTRATE_KEYSTORE_PASSWORD_ENV)?;
+            return decrypt_substrate_keystore(keystore, &password);
+        }
+        let phrase = std::env::var(SUBSTRATE_PHRASE_ENV)?;
+        Self::from_uri(key_type, &phrase)
+    }
+
+    pub fn account_id(&self) -> AccountId32 {
+        match self {
+            SubstrateSigner::Sr25519(keypair) => keypair.public_key().into(),
+            SubstrateSigner::Ecdsa(keypair) => keypair.public_key().into(),
+            SubstrateSigner::Sr25519Secret(keypair) => AccountId32(keypair.public.to_bytes()),
+        }
+    }
+}
+
+impl Signer<SubstrateConfig> for SubstrateSigner {
+    fn account_id(&self) -> AccountId32 {
+        SubstrateSigner::account_id(self)
+    }
+
+    fn address(&self) -> MultiAddress<AccountId32, u32> {
+        MultiAddress::Id(SubstrateSigner::account_id(self))
+    }
+
+    fn sign(&self, signer_payload: &[u8]) -> MultiSignature {
+        match self {
+            SubstrateSigner::Sr25519(keypair) => keypair.sign(signer_payload).into(),
+            SubstrateSigner::Ecdsa(keypair) => keypair.sign(signer_payload).into(),
+            SubstrateSigner::Sr25519Secret(keypair) => {
+                MultiSignature::Sr25519(keypair.sign_simple(SIGNING_CONTEXT, signer_payload).to_bytes())
+            }
+        }
+    }
+}
@@ -7,31 +7,56 @@ use subxt::config::SubstrateConfig;
 use subxt::dynamic::Value;
 use subxt::utils::{AccountId32, H256};
 use subxt::{Config, OnlineClient, PolkadotConfig};
-use subxt_signer::ecdsa::{Keypair, Seed, Signature};
+use subxt_signer::ecdsa::Signature;
 use subxt::config::polkadot::PolkadotExtrinsicParamsBuilder as Params;
-use subxt_signer::bip39::Mnemonic;
 use subxt_signer::sr25519::dev;
 use crate::traits::{EthereumOP, EtherumAction};
-use thea_primitives::types::SignedMessage;
+use thea_primitives::types::{PayloadType, SignedMessage, Withdraw};
+use tokio::sync::watch;
 use tokio::sync::mpsc::UnboundedSender;
+use futures::{Stream, StreamExt};
+use subxt::error::DispatchError;
+use subxt::tx::TxProgress;
 use crate::error::RelayerError;
+use crate::checkpoint::CheckpointStore;
+use crate::signer::SubstrateSigner;
+
+/// Blocks a submitted extrinsic stays valid for
+pub const MORTAL_PERIOD: u64 = 32;
+/// Finalized blocks between polls of the signed outgoing nonce backing up the event subscription
+const WITHDRAWAL_RECONCILE_BLOCKS: u64 = 50;
 
 #[subxt::subxt(runtime_metadata_path = "src/metadata.scale")]
 pub mod polkadex {}
 
+/// Signed outgoing message decoded once from its raw storage bytes into the type the contract is sent
+async fn fetch_signed_message(
+    storage: &subxt::storage::Storage<SubstrateConfig, OnlineClient<SubstrateConfig>>,
+    network_id: u8,
+    nonce: u64,
+) -> Result<Option<SignedMessage<sp_core::ecdsa::Signature>>, RelayerError> {
+    let signed_message_query = subxt::dynamic::storage(
+        "Thea",
+        "SignedOutgoingMessages",
+        vec![Value::u128(network_id.into()), Value::u128(nonce.into())],
+    );
+    match storage.fetch(&signed_message_query).await? {
+        Some(signed_message) => Ok(Some(Decode::decode(&mut &signed_message.into_encoded()[..])?)),
+        None => Ok(None),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SubstrateClient {
     client: OnlineClient<SubstrateConfig>,
-    signer: Keypair,
+    signer: SubstrateSigner,
+    network_id: u8,
 }
 
 impl SubstrateClient {
-    pub async fn initialize(url: String) -> Result<Self, RelayerError> {
+    pub async fn initialize(url: String, signer: SubstrateSigner, network_id: u8) -> Result<Self, RelayerError> {
         let api = OnlineClient::<SubstrateConfig>::from_url(url).await?;
-        let seed: Seed = Seed::from(H256::from_low_u64_be(10));
-        let signer = subxt_signer::ecdsa::Keypair::from_seed(seed)?;
-        let public_key = signer.public_key();
-        println!("Public Key {:?}", hex::encode(public_key));
+        println!("Relayer Account {:?}", signer.account_id());
         let update_task = api.updater();
         tokio::spawn(async move {
             update_task
@@ -42,137 +67,221 @@ impl SubstrateClient {
         Ok(Self {
             client: api,
             signer,
+            network_id,
         })
     }
-    pub async fn handle_deposit(
-        &self,
+    pub fn evm_deposit(
         deposit: EvmDeposit,
-    ) -> Result<(), RelayerError> {
-        println!("Handling Deposit Event");
-        let network_id = 255u8; //TODO: Config network Id
-        let incoming_nonce_query =
-            subxt::dynamic::storage("Thea", "IncomingNonce", vec![network_id]);
-        let incoming_nonce = if let Ok(incoming_nonce) = self
-            .client
-            .storage()
-            .at_latest()
-            .await?
-            .fetch(&incoming_nonce_query)
-            .await?
-            .ok_or(RelayerError::UnableToFetchIncomingNonce) {
-            let incoming_nonce: u64 = Decode::decode(&mut &incoming_nonce.into_encoded()[..])?;
-            incoming_nonce
-        } else {
-            0
-        };
-        println!("Incoming Nonce {:?}", incoming_nonce);
+    ) -> Result<thea_primitives::types::Deposit<AccountId32>, RelayerError> {
         let recipient_add: [u8; 32] = deposit.recipient.try_into().map_err(|_| RelayerError::FailedToConvertAddress)?;
         let recipient_add: AccountId32 = AccountId32::from(recipient_add);
-        let deposit = thea_primitives::types::Deposit {
+        Ok(thea_primitives::types::Deposit {
             id: deposit.outgoing_nonce.encode(),
             recipient: recipient_add,
             asset_id: deposit.asset_id,
             amount: deposit.amount,
             extra: Default::default(),
+        })
+    }
+
+    pub fn network_id(&self) -> u8 {
+        self.network_id
+    }
+
+    /// Last incoming nonce of the network, at the given block or the latest finalized one
+    pub async fn incoming_nonce(&self, block_hash: Option<H256>) -> Result<u64, RelayerError> {
+        let storage = match block_hash {
+            Some(block_hash) => self.client.storage().at(block_hash),
+            None => self.client.storage().at_latest().await?,
         };
-        let deposit_vec = vec![deposit];
+        let incoming_nonce_query =
+            subxt::dynamic::storage("Thea", "IncomingNonce", vec![self.network_id]);
+        match storage.fetch(&incoming_nonce_query).await? {
+            Some(incoming_nonce) => Ok(Decode::decode(&mut &incoming_nonce.into_encoded()[..])?),
+            None => Ok(0),
+        }
+    }
+
+    /// Signs and submits an incoming message, returning its progress and the block number its mortality starts from
+    pub async fn submit_incoming_message(
+        &self,
+        nonce: u64,
+        data: Vec<u8>,
+        block_no: u64,
+    ) -> Result<(TxProgress<SubstrateConfig, OnlineClient<SubstrateConfig>>, u64), RelayerError> {
+        println!("Submitting incoming message {:?} for EVM block {:?}", nonce, block_no);
         let message = polkadex::runtime_types::thea_primitives::types::Message {
-            block_no: 0,
-            nonce: incoming_nonce.saturating_add(1),
-            network: network_id,
-            data: deposit_vec.encode(),
+            block_no,
+            nonce,
+            network: self.network_id,
+            data,
             payload_type: polkadex::runtime_types::thea_primitives::types::PayloadType::L1Deposit,
         };
         let thea_deposit_tx = polkadex::tx().thea().submit_incoming_message(message, 1_100_000_000_000u128);
-        let phrase = "lens secret trick castle amused fresh panel door table merit dance element";
-        let mnemonic = Mnemonic::parse(phrase).unwrap();
-        let from = subxt_signer::sr25519::Keypair::from_phrase(&mnemonic, None).unwrap();
         let latest_block = self.client.blocks().at_latest().await?;
         let tx_params = Params::new()
             .tip(1_000)
-            .mortal(latest_block.header(), 32)
+            .mortal(latest_block.header(), MORTAL_PERIOD)
             .build();
-        let result = self
+        let progress = self
             .client
             .tx()
-            .sign_and_submit(&thea_deposit_tx, &from, tx_params)
+            .sign_and_submit_then_watch(&thea_deposit_tx, &self.signer, tx_params)
             .await?;
-        println!("Deposit Transaction {:?}", result);
-        Ok(())
+        println!("Deposit Transaction {:?}", progress.extrinsic_hash());
+        Ok((progress, u64::from(latest_block.number())))
     }
 
+    /// Follows a submitted extrinsic until it is finalized, returning the finalized block hash
+    pub async fn wait_for_finalized(
+        progress: TxProgress<SubstrateConfig, OnlineClient<SubstrateConfig>>,
+    ) -> Result<H256, RelayerError> {
+        let in_block = progress.wait_for_finalized().await?;
+        let block_hash = in_block.block_hash();
+        match in_block.wait_for_success().await {
+            Ok(_) => Ok(block_hash),
+            Err(subxt::Error::Runtime(error)) => {
+                if let DispatchError::Module(module_error) = &error {
+                    if let Ok(polkadex::Error::Thea(error)) = module_error.as_root_error::<polkadex::Error>() {
+                        return Err(RelayerError::TheaError(error));
+                    }
+                }
+                Err(RelayerError::ExtrinsicFailed(error.to_string()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Withdrawals carried by a signed outgoing message, read at the given finalized block
+    pub async fn outgoing_withdrawals(&self, nonce: u64, block_hash: H256) -> Result<Vec<Withdraw>, RelayerError> {
+        let message = fetch_signed_message(&self.client.storage().at(block_hash), self.network_id, nonce)
+            .await?
+            .ok_or(RelayerError::OutgoingMessageNotFound(nonce))?;
+        // Other payloads, like validator set rotations, carry no withdrawals
+        if !matches!(message.message.payload_type, PayloadType::L1Deposit) {
+            return Ok(vec![]);
+        }
+        Ok(Decode::decode(&mut &message.message.data[..])?)
+    }
+
+    /// Current Thea validator set id on the latest finalized block
+    pub async fn validator_set_id(&self) -> Result<u64, RelayerError> {
+        let validator_set_id_query = polkadex::storage().thea().validator_set_id();
+        Ok(self
+            .client
+            .storage()
+            .at_latest()
+            .await?
+            .fetch_or_default(&validator_set_id_query)
+            .await?)
+    }
+
+    /// Number and hash of every finalized block
+    pub async fn subscribe_finalized_blocks(
+        &self,
+    ) -> Result<impl Stream<Item = Result<(u64, H256), RelayerError>> + Unpin, RelayerError> {
+        let blocks = self.client.blocks().subscribe_finalized().await?;
+        Ok(blocks.map(|block| -> Result<(u64, H256), RelayerError> {
+            let block = block?;
+            Ok((u64::from(block.number()), block.hash()))
+        }))
+    }
+
+    /// Relays signed outgoing messages announced by `TheaSignatureFinalized` events of each finalized block
     pub async fn subscribe_substrate_event_stream(
         &self,
         sender: UnboundedSender<TheaMessage>,
+        checkpoint: CheckpointStore,
+        default_outgoing_nonce: u64,
+        known_validator_set: watch::Receiver<u64>,
     ) -> Result<(), RelayerError> {
-        let network_id = 255u8; //TODO: Config network Id
+        let network_id = self.network_id;
         // Fetch Outgoing nonce
         //OutgoingNonce
         println!("Subscribing to Withdrawal Events");
         let mut blocks_sub = self.client.blocks().subscribe_finalized().await?;
-        let mut processed_finalised_outgoing_nonce: u64 = 106; //TODO: Should read from local storage
+        let mut processed_finalised_outgoing_nonce: u64 = checkpoint
+            .last_outgoing_nonce(network_id)?
+            .unwrap_or(default_outgoing_nonce);
+        println!("Resuming withdrawals after nonce {:?}", processed_finalised_outgoing_nonce);
+        // Highest nonce known to be signed, messages up to it are relayed in order
+        let mut signed_outgoing_nonce = processed_finalised_outgoing_nonce;
+        let mut reconciled_at: Option<u64> = None;
         while let Some(block) = blocks_sub.next().await {
             let block = block?;
-            let block_hash = block.hash();
-            let storage_query = subxt::dynamic::storage("Thea", "SignedOutgoingNonce", vec![network_id]);
-            let latest_signed_outgoing_nonce = self.client.storage().at_latest().await?.fetch(&storage_query).await?.unwrap(); //FIXME: Remove unwrap
-            let latest_signed_outgoing_nonce: u64 = Decode::decode(&mut &latest_signed_outgoing_nonce.into_encoded()[..])?;
-            if latest_signed_outgoing_nonce > processed_finalised_outgoing_nonce {
-                let storage_query = polkadex::storage().thea().signed_outgoing_messages(network_id, latest_signed_outgoing_nonce);
-                if let Some(result) = self.client
-                    .storage()
-                    .at_latest()
-                    .await?
-                    .fetch(&storage_query)
-                    .await? {
-                    println!("Message found {:?}", result);
-                    let message: SignedMessage<sp_core::ecdsa::Signature> = Decode::decode(&mut &result.encode()[..])?;
-                    //Convert BTreeMap to Vec<(a,b)>
-                    let signatures: Vec<(u32, sp_core::ecdsa::Signature)> = message.signatures.into_iter().map(|(a,b)| (a,b)).collect();
-                    println!("Message {:?}", hex::encode(message.message.encode().clone()));
-                    // Send message using channel
-                    sender.send(TheaMessage::SubstrateMessageWithProof(message.message.encode(), message.validator_set_id,signatures))?;
-                    processed_finalised_outgoing_nonce = latest_signed_outgoing_nonce; //TODO: Also update the local storage
+            let block_number = u64::from(block.number());
+            // Every read is pinned to this finalized block so all relayers see the same messages
+            let storage = self.client.storage().at(block.hash());
+            for event in block.events().await?.find::<polkadex::thea::events::TheaSignatureFinalized>() {
+                let polkadex::thea::events::TheaSignatureFinalized(event_network_id, nonce) = event?;
+                if event_network_id == network_id {
+                    println!("Outgoing message {:?} signed at block {:?}", nonce, block_number);
+                    signed_outgoing_nonce = signed_outgoing_nonce.max(nonce);
+                }
+            }
+            // Falls back to the nonce storage on start and periodically, in case events were missed
+            if reconciled_at.map_or(true, |reconciled_at| block_number >= reconciled_at.saturating_add(WITHDRAWAL_RECONCILE_BLOCKS)) {
+                let signed_outgoing_nonce_query = polkadex::storage().thea().signed_outgoing_nonce(network_id);
+                let latest_signed_outgoing_nonce: u64 = storage.fetch(&signed_outgoing_nonce_query).await?.unwrap_or_default();
+                if latest_signed_outgoing_nonce > signed_outgoing_nonce {
+                    println!("Reconciled signed outgoing nonce {:?} missed by events", latest_signed_outgoing_nonce);
+                    signed_outgoing_nonce = latest_signed_outgoing_nonce;
+                }
+                reconciled_at = Some(block_number);
+            }
+            // Walk every nonce signed since the last processed one, in order
+            for nonce in processed_finalised_outgoing_nonce.saturating_add(1)..=signed_outgoing_nonce {
+                let message = match fetch_signed_message(&storage, network_id, nonce).await? {
+                    Some(message) => message,
+                    None => {
+                        // Gap in the signed range, retry from this nonce on the next finalized block
+                        println!("Signed outgoing message missing for network {:?} nonce {:?}", network_id, nonce);
+                        break;
+                    }
+                };
+                println!("Signed outgoing message {:?} found", nonce);
+                // Withdrawals signed by a set the contract does not know yet wait for the rotation to be relayed
+                let known_validator_set_id = *known_validator_set.borrow();
+                if message.validator_set_id > known_validator_set_id {
+                    println!(
+                        "Holding nonce {:?} signed by validator set {:?}, contract knows up to {:?}",
+                        nonce, message.validator_set_id, known_validator_set_id
+                    );
+                    break;
+                }
+                if matches!(message.message.payload_type, PayloadType::ScheduledRotateValidators) {
+                    println!("Relaying validator set rotation at nonce {:?}", nonce);
                 }
+                //Convert BTreeMap to Vec<(a,b)>
+                let signatures: Vec<(u32, sp_core::ecdsa::Signature)> = message.signatures.into_iter().map(|(a,b)| (a,b)).collect();
+                println!("Message {:?}", hex::encode(message.message.encode().clone()));
+                // Send message using channel
+                let message = TheaMessage::SubstrateMessageWithProof(message.message.encode(), message.validator_set_id,signatures);
+                checkpoint.add_in_flight(&message)?;
+                checkpoint.set_last_outgoing_nonce(network_id, nonce)?;
+                sender.send(message)?;
+                processed_finalised_outgoing_nonce = nonce;
             }
+            checkpoint.set_last_finalized_block(network_id, block.hash().0)?;
         }
         Ok(())
     }
 
-    pub async fn handle_ob_deposit(
-        &self,
+    /// Credits the main account and names the trading account as its proxy in `extra`
+    pub fn orderbook_deposit(
         deposit: ObEvmDeposit,
-    ) -> Result<(), RelayerError> {
-        let network_id = 2u8; //TODO: Config network Id
-        let incoming_nonce_query =
-            subxt::dynamic::storage("Thea", "IncomingNonce", vec![network_id]);
-        let incoming_nonce = self
-            .client
-            .storage()
-            .at_latest()
-            .await?
-            .fetch(&incoming_nonce_query)
-            .await?
-            .ok_or(RelayerError::UnableToFetchIncomingNonce)?
-            .into_encoded();
-        let incoming_nonce: u64 = Decode::decode(&mut &incoming_nonce[..])?;
-        let deposit = thea_primitives::types::Deposit {
-            id: Default::default(),
-            recipient: deposit.main_account,
+    ) -> Result<thea_primitives::types::Deposit<AccountId32>, RelayerError> {
+        let main_account: [u8; 32] = deposit.main_account.try_into().map_err(|_| RelayerError::FailedToConvertAddress)?;
+        let main_account = AccountId32::from(main_account);
+        let trading_account: [u8; 32] = deposit.trading_account.try_into().map_err(|_| RelayerError::FailedToConvertAddress)?;
+        let trading_account = AccountId32::from(trading_account);
+        let action = EtherumAction::DepositToOrderbook(deposit.asset_id, deposit.amount, main_account.clone(), trading_account);
+        Ok(thea_primitives::types::Deposit {
+            id: deposit.id,
+            recipient: main_account,
             asset_id: deposit.asset_id,
             amount: deposit.amount,
-            extra: Default::default(),
-        };
-        let deposit_vec = vec![deposit];
-        let message = polkadex::runtime_types::thea_primitives::types::Message {
-            block_no: 0,
-            nonce: incoming_nonce,
-            data: deposit_vec.encode(),
-            network: 1,
-            payload_type: polkadex::runtime_types::thea_primitives::types::PayloadType::L1Deposit,
-        };
-        let thea_deposit_tx = polkadex::tx().thea().submit_incoming_message(message, 1u128);
-        println!("Submitting Deposit to Substrate {:?}", thea_deposit_tx);
-        Ok(())
+            extra: action.encode(),
+        })
     }
 }
@@ -0,0 +1,103 @@
+use crate::error::RelayerError;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::{AbortHandle, JoinHandle};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Owns the long running relayer tasks and restarts them when they fail
+pub struct Supervisor {
+    tasks: Vec<JoinHandle<()>>,
+    fatal_sender: UnboundedSender<(&'static str, RelayerError)>,
+    fatal_receiver: UnboundedReceiver<(&'static str, RelayerError)>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let (fatal_sender, fatal_receiver) = unbounded_channel();
+        Self {
+            tasks: vec![],
+            fatal_sender,
+            fatal_receiver,
+        }
+    }
+
+    /// Runs the task produced by `task`, restarting it with backoff until it fails fatally
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), RelayerError>> + Send + 'static,
+    {
+        let fatal_sender = self.fatal_sender.clone();
+        self.tasks.push(tokio::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                let started = Instant::now();
+                // Spawned separately so a panic is reported as a failed run instead of killing the supervisor
+                let attempt = tokio::spawn(task());
+                let _guard = AbortOnDrop(attempt.abort_handle());
+                match attempt.await {
+                    Ok(Ok(())) => println!("{} stopped, restarting", name),
+                    Ok(Err(err)) if err.is_fatal() => {
+                        println!("{} failed fatally: {:?}", name, err);
+                        let _ = fatal_sender.send((name, err));
+                        return;
+                    }
+                    Ok(Err(err)) => println!("{} failed: {:?}, restarting in {:?}", name, err, backoff),
+                    Err(err) => println!("{} panicked: {:?}, restarting in {:?}", name, err, backoff),
+                }
+                // A task that ran for a while before failing starts over with the initial backoff
+                if started.elapsed() > MAX_BACKOFF {
+                    backoff = INITIAL_BACKOFF;
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }));
+    }
+
+    /// Resolves once a supervised task fails with an error restarting cannot recover from
+    pub async fn fatal_error(&mut self) -> (&'static str, RelayerError) {
+        // The supervisor holds a sender itself, so the channel is never closed
+        loop {
+            if let Some(fatal) = self.fatal_receiver.recv().await {
+                return fatal;
+            }
+        }
+    }
+
+    pub async fn shutdown(self) {
+        for task in self.tasks {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Aborts the running attempt when the supervising task itself is aborted
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM
+pub async fn shutdown_signal() -> Result<(), RelayerError> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
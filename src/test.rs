@@ -4,6 +4,29 @@ use k256::ecdsa;
 use sp_core::{H256, Pair};
 use ethers::utils::hex;
 use sp_core::ecdsa::Signature;
+use crate::checkpoint::{CheckpointStore, EvmCursor, EvmStream, EvmTxStatus, PendingClaim, PendingIncoming};
+use crate::evmclient::{decode_revert, deposit_amount, EvmClient, PendingWithdrawal};
+use crate::claimer::due_withdrawals;
+use crate::watchtower::{withdrawal_mismatch, withdrawals_to_block, ExpectedWithdrawal};
+use crate::withdrawal::WithdrawalRelay;
+use crate::noncemanager::IncomingNonceManager;
+use crate::traits::{EtherumAction, EvmDeposit, ObEvmDeposit, TheaMessage};
+use crate::substrateclient::{SubstrateClient, MORTAL_PERIOD};
+use crate::batcher::DepositBatcher;
+use crate::confirmation::{DepositConfirmation, PendingDeposits};
+use parity_scale_codec::{Decode, Encode};
+use subxt::utils::AccountId32;
+use crate::error::RelayerError;
+use crate::keystore::{decrypt_substrate_keystore, load_evm_seed, read_password};
+use crate::signer::SubstrateSigner;
+use crate::evmsigner::{LocalSigner, RelayerSigner, RemoteSigner};
+use ethers::prelude::TransactionRequest;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, U256};
+use crate::feestrategy::FeeStrategy;
+use crate::proof::{check_validator_set, verify_signatures};
+use std::io::{Read, Write};
+use std::time::Duration;
 
 #[test]
 fn test_thea_sig() {
@@ -232,4 +255,749 @@ fn create_payload() {
     }
 
 
-}
\ No newline at end of file
+}
+
+fn temporary_checkpoint() -> CheckpointStore {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    CheckpointStore::new(db)
+}
+
+#[test]
+fn test_checkpoint_cursors() {
+    let checkpoint = temporary_checkpoint();
+    assert_eq!(checkpoint.last_outgoing_nonce(1).unwrap(), None);
+    assert_eq!(checkpoint.evm_cursor(EvmStream::Deposit).unwrap(), None);
+    checkpoint.set_last_outgoing_nonce(1, 42).unwrap();
+    checkpoint.set_evm_cursor(EvmStream::Deposit, EvmCursor::new(100, 3)).unwrap();
+    assert_eq!(checkpoint.last_outgoing_nonce(1).unwrap(), Some(42));
+    assert_eq!(checkpoint.last_outgoing_nonce(2).unwrap(), None);
+    assert_eq!(checkpoint.evm_cursor(EvmStream::Deposit).unwrap(), Some(EvmCursor::new(100, 3)));
+    assert_eq!(checkpoint.evm_cursor(EvmStream::ObDeposit).unwrap(), None);
+    assert!(EvmCursor::new(100, 3) < EvmCursor::new(101, 0));
+}
+
+#[test]
+fn test_checkpoint_in_flight() {
+    let checkpoint = temporary_checkpoint();
+    let first = TheaMessage::EvmDeposit(EvmDeposit::new(vec![2; 32], 1, 100, 2, 10));
+    let second = TheaMessage::EvmDeposit(EvmDeposit::new(vec![1; 32], 1, 100, 1, 10));
+    checkpoint.add_in_flight(&first).unwrap();
+    checkpoint.add_in_flight(&second).unwrap();
+    let in_flight = checkpoint.in_flight().unwrap();
+    assert_eq!(in_flight.len(), 2);
+    assert!(matches!(&in_flight[0], TheaMessage::EvmDeposit(deposit) if deposit.outgoing_nonce == 2));
+    checkpoint.remove_in_flight(CheckpointStore::in_flight_key(&first).unwrap()).unwrap();
+    let in_flight = checkpoint.in_flight().unwrap();
+    assert_eq!(in_flight.len(), 1);
+    assert!(matches!(&in_flight[0], TheaMessage::EvmDeposit(deposit) if deposit.outgoing_nonce == 1));
+}
+
+#[test]
+fn test_dead_letters() {
+    let checkpoint = temporary_checkpoint();
+    let message = TheaMessage::EvmDeposit(EvmDeposit::new(vec![1; 32], 1, 100, 1, 10));
+    assert!(checkpoint.dead_letters().unwrap().is_empty());
+    checkpoint.add_dead_letter(&message, &RelayerError::FailedToConvertAddress, 1).unwrap();
+    let dead_letters = checkpoint.dead_letters().unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].attempts, 1);
+    assert_eq!(dead_letters[0].error, "Failed to convert address");
+}
+
+#[test]
+fn test_error_classification() {
+    assert!(RelayerError::UnableToFetchIncomingNonce.is_retryable());
+    assert!(!RelayerError::FailedToConvertAddress.is_retryable());
+    assert!(!RelayerError::FailedToConvertAddress.is_fatal());
+    assert!(RelayerError::IoError(std::io::Error::from(std::io::ErrorKind::Other)).is_fatal());
+    assert!(RelayerError::WithdrawalRejected(3, "Not Enough Signatures".to_string()).is_fatal());
+    let nonce_mismatch = RelayerError::TheaError(crate::substrateclient::polkadex::thea::Error::MessageNonce);
+    assert!(nonce_mismatch.is_retryable() && nonce_mismatch.is_nonce_mismatch());
+    assert!(!RelayerError::ExtrinsicFailed("BadOrigin".to_string()).is_nonce_mismatch());
+}
+
+#[test]
+fn test_evm_keystore() {
+    let dir = std::env::temp_dir().join("relayer_test_evm_keystore");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut rng = ethers::core::rand::thread_rng();
+    let (wallet, _) = LocalWallet::new_keystore(&dir, &mut rng, "password", Some("key.json")).unwrap();
+    let password_file = dir.join("password");
+    std::fs::write(&password_file, "password\n").unwrap();
+    assert_eq!(read_password(Some(password_file.clone()), "UNUSED").unwrap(), "password");
+    let seed = load_evm_seed(None, Some(dir.join("key.json")), Some(password_file)).unwrap();
+    let decrypted: LocalWallet = seed.as_str().parse().unwrap();
+    assert_eq!(decrypted.address(), wallet.address());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// `//Alice` dev keys in the polkadot-js encrypted JSON format (scrypt N=2^15, xsalsa20-poly1305), password "relayer"
+const ALICE_SR25519_EXPORT: &str = r#"{"encoded": "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8AgAAAAQAAAAgAAABkZWZnaGlqa2xtbm9wcXJzdHV2d3h5ensaGV4ffWcFGXhPEJVGp2r3Ls2jj86TIY7Lc9pMhxKIJMpFDxH2SxoZ2UNyuoSd3EmFGkClJJtz+qwD6F4J2yEiYFQwmPhWbuaDgJeY7YQqAnmk16OyTEgeQ0vGcCEyzNVUGYD+ENWj6xNGRffL+d/Xoc4SiLdVv9aZ63BdtxlD3Z+N6s03", "encoding": {"content": ["pkcs8", "sr25519"], "type": ["scrypt", "xsalsa20-poly1305"], "version": "3"}, "meta": {"name": "alice"}}"#;
+const ALICE_ECDSA_EXPORT: &str = r#"{"encoded": "ICEiIyQlJicoKSorLC0uLzAxMjM0NTY3ODk6Ozw9Pj8AgAAAAQAAAAgAAACCg4SFhoeIiYqLjI2Oj5CRkpOUlZaXmJlkhkj0ETfvH57EPcBcfp3/e5oL4qaFO1sxVcli9XaQMKXvdznyZA+YAwnBtHjgfBZ/kYgM0vDGenxdD7ZsHAbdyCUulOZwhxfVUieFg7woJPdgfdDXryJp9KXUfv75WsI/v5NhBtM=", "encoding": {"content": ["pkcs8", "ecdsa"], "type": ["scrypt", "xsalsa20-poly1305"], "version": "3"}, "meta": {"name": "alice"}}"#;
+
+#[test]
+fn test_substrate_keystore() {
+    let dir = std::env::temp_dir().join("relayer_test_substrate_keystore");
+    std::fs::create_dir_all(&dir).unwrap();
+    let decrypt = |name: &str, export: &str, password: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, export).unwrap();
+        decrypt_substrate_keystore(path, password)
+    };
+    let alice_sr25519 = decrypt("sr25519.json", ALICE_SR25519_EXPORT, "relayer").unwrap();
+    assert!(matches!(alice_sr25519, SubstrateSigner::Sr25519Secret(_)));
+    let alice_sr25519_public = hex::decode("d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d").unwrap();
+    assert_eq!(alice_sr25519.account_id().0.to_vec(), alice_sr25519_public);
+    let alice_ecdsa = decrypt("ecdsa.json", ALICE_ECDSA_EXPORT, "relayer").unwrap();
+    let alice_ecdsa_public = hex::decode("020a1091341fe5664bfa1782d5e04779689068c916b04cb365ec3153755684d9a1").unwrap();
+    assert_eq!(alice_ecdsa.account_id().0, sp_core::hashing::blake2_256(&alice_ecdsa_public));
+    assert!(matches!(
+        decrypt("wrong_password.json", ALICE_ECDSA_EXPORT, "wrong"),
+        Err(RelayerError::KeystoreError(_))
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Answers a single HTTP request with the body built by `respond`, handing back the request line and JSON body
+fn mock_http_server<F>(respond: F) -> (String, std::thread::JoinHandle<(String, serde_json::Value)>)
+where
+    F: FnOnce(&serde_json::Value) -> String + Send + 'static,
+{
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut buffer = vec![];
+        let (request_line, request) = loop {
+            let mut chunk = [0u8; 4096];
+            let read = socket.read(&mut chunk).unwrap();
+            buffer.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&buffer).to_string();
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let length = text[..header_end]
+                    .lines()
+                    .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|length| length.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                let body_start = header_end + 4;
+                if buffer.len() >= body_start + length {
+                    let request_line = text.lines().next().unwrap().to_string();
+                    break (request_line, serde_json::from_slice::<serde_json::Value>(&buffer[body_start..body_start + length]).unwrap());
+                }
+            }
+        };
+        let body = respond(&request);
+        let response = format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+        socket.write_all(response.as_bytes()).unwrap();
+        (request_line, request)
+    });
+    (url, handle)
+}
+
+/// Answers a single JSON-RPC request with `result`
+fn mock_rpc_server(result: serde_json::Value) -> (String, std::thread::JoinHandle<(String, serde_json::Value)>) {
+    mock_http_server(move |request| serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": result}).to_string())
+}
+
+/// EVM node over WebSocket answering every JSON-RPC request with `respond(method, params)`
+async fn mock_evm_node<F>(respond: F) -> String
+where
+    F: Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync + 'static,
+{
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let respond = std::sync::Arc::new(respond);
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let mut socket = tokio_tungstenite::accept_async(socket).await.unwrap();
+                while let Some(Ok(message)) = socket.next().await {
+                    let WsMessage::Text(text) = message else {
+                        continue;
+                    };
+                    let request: serde_json::Value = serde_json::from_str(&text).unwrap();
+                    let result = respond(request["method"].as_str().unwrap(), &request["params"]);
+                    let response = serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+                    if socket.send(WsMessage::Text(response.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    url
+}
+
+#[tokio::test]
+async fn test_remote_signer() {
+    let wallet: LocalWallet = "c05c6ae125754dd17f36bcc5318498ce5c6c2f0e9e1116c68b77889a8be2ff02".parse().unwrap();
+    let local = LocalSigner::new(wallet.clone());
+    let tx: TypedTransaction = TransactionRequest::new()
+        .to(Address::zero())
+        .value(1)
+        .nonce(0)
+        .gas(21000)
+        .gas_price(1)
+        .chain_id(11155111u64)
+        .into();
+    let signed_tx = local.sign_transaction(&tx).await.unwrap();
+    // Web3Signer answers with the raw transaction
+    let (url, request) = mock_rpc_server(serde_json::json!(signed_tx));
+    let remote = RemoteSigner::new(&url, wallet.address()).unwrap();
+    assert_eq!(remote.address(), local.address());
+    assert_eq!(remote.sign_transaction(&tx).await.unwrap(), signed_tx);
+    let (_, request) = request.join().unwrap();
+    assert_eq!(request["method"], "eth_signTransaction");
+    assert_eq!(request["params"][0]["from"], serde_json::json!(wallet.address()));
+    // Clef and geth wrap it along with the decoded transaction
+    let (url, request) = mock_rpc_server(serde_json::json!({"raw": signed_tx, "tx": {"nonce": "0x0"}}));
+    let remote = RemoteSigner::new(&url, wallet.address()).unwrap();
+    assert_eq!(remote.sign_transaction(&tx).await.unwrap(), signed_tx);
+    request.join().unwrap();
+
+    let message = b"thea message".to_vec();
+    let signature = local.sign_message(&message).await.unwrap();
+    assert_eq!(signature.recover(ethers::types::H256(ethers::utils::keccak256(&message))).unwrap(), wallet.address());
+    let response = format!("0x{}", hex::encode(signature.to_vec()));
+    let (url, request) = mock_http_server(move |_| response);
+    let remote = RemoteSigner::new(&url, wallet.address()).unwrap();
+    assert_eq!(remote.sign_message(&message).await.unwrap(), signature);
+    let (request_line, request) = request.join().unwrap();
+    assert_eq!(request_line, format!("POST /api/v1/eth1/sign/{:?} HTTP/1.1", wallet.address()));
+    assert_eq!(request["data"], serde_json::json!(Bytes::from(message)));
+}
+
+/// Calldata of an `eth_call` request
+fn call_data(params: &serde_json::Value) -> &str {
+    params[0]["data"].as_str().or_else(|| params[0]["input"].as_str()).unwrap_or_default()
+}
+
+/// Client of EVM network 1 on a mock node, requests past the connection checks are answered by `respond`
+async fn mock_evm_client<F>(wallet: LocalWallet, fee_strategy: FeeStrategy, respond: F) -> EvmClient
+where
+    F: Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync + 'static,
+{
+    let network_id_call = format!("0x{}", hex::encode(ethers::utils::id("networkId()")));
+    let url = mock_evm_node(move |method, params| match method {
+        "eth_chainId" => serde_json::json!("0xaa36a7"),
+        "eth_call" if call_data(params).starts_with(&network_id_call) => serde_json::json!(format!("0x{:064x}", 1)),
+        method => respond(method, params),
+    })
+    .await;
+    let contract = ethers::abi::Contract::load(&include_bytes!("../thea_abi.json")[..]).unwrap();
+    EvmClient::new(
+        url,
+        contract,
+        std::sync::Arc::new(LocalSigner::new(wallet)),
+        format!("{:?}", Address::zero()),
+        1,
+        None,
+        None,
+        1000,
+        1,
+        fee_strategy,
+        DepositConfirmation::default(),
+    )
+    .await
+    .unwrap()
+}
+
+/// Answers everything sending a transaction needs, keeping the raw transactions broadcast in `sent`
+fn mock_tx_node(
+    sent: std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>>,
+    from: Address,
+    gas_price: u64,
+) -> impl Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync + 'static {
+    move |method, params| {
+        let mut sent = sent.lock().unwrap();
+        match method {
+            "eth_estimateGas" => serde_json::json!("0x5208"),
+            "eth_gasPrice" => serde_json::json!(format!("0x{:x}", gas_price)),
+            // The account already sent 7 transactions before the relayer started
+            "eth_getTransactionCount" => serde_json::json!(format!("0x{:x}", 7 + sent.len())),
+            "eth_sendRawTransaction" => {
+                let raw = hex::decode(params[0].as_str().unwrap().trim_start_matches("0x")).unwrap();
+                let tx_hash = ethers::types::H256(ethers::utils::keccak256(&raw));
+                sent.push(raw);
+                serde_json::json!(tx_hash)
+            }
+            "eth_getTransactionReceipt" => serde_json::json!({
+                "transactionHash": params[0],
+                "transactionIndex": "0x0",
+                "blockHash": ethers::types::H256::repeat_byte(1),
+                "blockNumber": "0x10",
+                "from": from,
+                "to": Address::zero(),
+                "cumulativeGasUsed": "0x5208",
+                "gasUsed": "0x5208",
+                "logs": [],
+                "logsBloom": format!("0x{}", "00".repeat(256)),
+                "status": "0x1",
+            }),
+            "eth_blockNumber" => serde_json::json!("0x10"),
+            method => panic!("Unexpected request {:?}", method),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_consecutive_nonces() {
+    let wallet: LocalWallet = "c05c6ae125754dd17f36bcc5318498ce5c6c2f0e9e1116c68b77889a8be2ff02".parse().unwrap();
+    let sent: std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>> = Default::default();
+    let node = mock_tx_node(sent.clone(), wallet.address(), 1_000_000_000);
+    let legacy = FeeStrategy { legacy: true, ..FeeStrategy::default() };
+    let client = mock_evm_client(wallet, legacy, node).await;
+    let first = client.claim_withdrawal(1, 0).await.unwrap();
+    let second = client.claim_withdrawal(1, 1).await.unwrap();
+    assert_eq!((first.nonce, second.nonce), (7, 8));
+    assert_eq!(first.status, EvmTxStatus::Confirmed(16));
+    // The broadcast transactions themselves carry those nonces
+    let nonces: Vec<_> = sent
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|raw| TypedTransaction::decode_signed(&ethers::utils::rlp::Rlp::new(raw)).unwrap().0.nonce().cloned())
+        .collect();
+    assert_eq!(nonces, vec![Some(7.into()), Some(8.into())]);
+}
+
+#[tokio::test]
+async fn test_urgent_transactions() {
+    let wallet: LocalWallet = "c05c6ae125754dd17f36bcc5318498ce5c6c2f0e9e1116c68b77889a8be2ff02".parse().unwrap();
+    let sent: std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>> = Default::default();
+    let node = mock_tx_node(sent.clone(), wallet.address(), 500);
+    let ceiling = FeeStrategy { legacy: true, gas_price_ceiling: Some(U256::from(100)), ..FeeStrategy::default() };
+    let client = mock_evm_client(wallet, ceiling, node).await;
+    // Claims pause while the gas price is above the ceiling
+    let claim = tokio::spawn({
+        let client = client.clone();
+        async move { client.claim_withdrawal(1, 0).await }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!claim.is_finished());
+    // Blocking a withdrawal goes out regardless
+    let outcome = client.block_transaction(1, 0).await.unwrap();
+    assert_eq!((outcome.nonce, outcome.status), (7, EvmTxStatus::Confirmed(16)));
+    assert_eq!(sent.lock().unwrap().len(), 1);
+    claim.abort();
+}
+
+#[tokio::test]
+async fn test_send_verified_proof() {
+    let wallet: LocalWallet = "c05c6ae125754dd17f36bcc5318498ce5c6c2f0e9e1116c68b77889a8be2ff02".parse().unwrap();
+    let sent: std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>> = Default::default();
+    let node = mock_tx_node(sent.clone(), wallet.address(), 1_000_000_000);
+    let legacy = FeeStrategy { legacy: true, ..FeeStrategy::default() };
+    let client = mock_evm_client(wallet, legacy, node).await;
+    let signatures: Vec<(u32, Signature)> = (0..3u8).map(|index| (index as u32, Signature::from_raw([index; 65]))).collect();
+    client.handle_substrate_message_with_proof(b"thea message".to_vec(), &[2, 0], signatures).await.unwrap();
+    let raw = sent.lock().unwrap()[0].clone();
+    let (tx, _) = TypedTransaction::decode_signed(&ethers::utils::rlp::Rlp::new(&raw)).unwrap();
+    let contract = ethers::abi::Contract::load(&include_bytes!("../thea_abi.json")[..]).unwrap();
+    let input = contract.function("sendMessage").unwrap().decode_input(&tx.data().unwrap()[4..]).unwrap();
+    let signature = |index: u8| ethers::abi::Token::Bytes(vec![index; 65]);
+    let index = |index: u64| ethers::abi::Token::Uint(U256::from(index));
+    // Only the signatures of the verified validators, in the order they were verified
+    assert_eq!(input[1], ethers::abi::Token::Array(vec![signature(2), signature(0)]));
+    assert_eq!(input[2], ethers::abi::Token::Array(vec![index(0), index(1), index(2)]));
+}
+
+#[tokio::test]
+async fn test_pending_withdrawals_bounded() {
+    let wallet: LocalWallet = "c05c6ae125754dd17f36bcc5318498ce5c6c2f0e9e1116c68b77889a8be2ff02".parse().unwrap();
+    let reads = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let node_reads = reads.clone();
+    let client = mock_evm_client(wallet, FeeStrategy::default(), move |method, params| {
+        assert_eq!(method, "eth_call");
+        node_reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        // Every index holds a withdrawal, only the range bounds the read
+        let index = &call_data(params)[2 + 8 + 64..2 + 8 + 128];
+        let recipient = format!("{:0>64}", hex::encode([7u8; 20]));
+        let words = [index.to_string(), format!("{:064x}", 1), format!("{:064x}", 500), recipient, format!("{:064x}", 0), format!("{:064x}", 0)];
+        serde_json::json!(format!("0x{}", words.concat()))
+    })
+    .await;
+    let withdrawals = client.pending_withdrawals(9, 1..4).await.unwrap();
+    assert_eq!(withdrawals.iter().map(|withdrawal| withdrawal.index).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(withdrawals[0], PendingWithdrawal { index: 1, asset_id: 1, amount: 500, recipient: Address::from([7; 20]), is_blocked: false });
+    assert_eq!(reads.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_deposit_amount() {
+    let meta = ethers::contract::LogMeta {
+        address: Address::zero(),
+        block_number: 10u64.into(),
+        block_hash: ethers::types::H256::zero(),
+        transaction_hash: ethers::types::H256::zero(),
+        transaction_index: 0u64.into(),
+        log_index: 3u64.into(),
+    };
+    assert_eq!(deposit_amount(U256::from(u128::MAX), &meta), Some(u128::MAX));
+    // Skipped rather than panicking and stalling every later deposit
+    assert_eq!(deposit_amount(U256::from(u128::MAX) + 1, &meta), None);
+}
+
+#[test]
+fn test_orderbook_deposit() {
+    let deposit = ObEvmDeposit::new(vec![9; 40], vec![1; 32], vec![2; 32], 1, 100, 10);
+    let deposit = SubstrateClient::orderbook_deposit(deposit).unwrap();
+    assert_eq!(deposit.id, vec![9; 40]);
+    assert_eq!(deposit.recipient, AccountId32([1; 32]));
+    let action = EtherumAction::<AccountId32>::decode(&mut &deposit.extra[..]).unwrap();
+    assert_eq!(action, EtherumAction::DepositToOrderbook(1, 100, AccountId32([1; 32]), AccountId32([2; 32])));
+    let invalid = ObEvmDeposit::new(vec![], vec![1; 20], vec![2; 32], 1, 100, 10);
+    assert!(matches!(SubstrateClient::orderbook_deposit(invalid), Err(RelayerError::FailedToConvertAddress)));
+}
+
+#[test]
+fn test_deposit_batcher() {
+    let deposit = |nonce: u64, block_number: u64| TheaMessage::EvmDeposit(EvmDeposit::new(vec![1; 32], 1, 100, nonce, block_number));
+    let mut batcher = DepositBatcher::new(2);
+    assert!(batcher.deadline().is_none());
+    assert_eq!(DepositBatcher::block_number(&deposit(1, 10)), Some(10));
+    assert_eq!(DepositBatcher::block_number(&TheaMessage::SubstrateMessage(vec![])), None);
+    assert!(batcher.push(deposit(1, 10), 10).is_none());
+    assert!(batcher.deadline().is_some());
+    assert!(batcher.push(deposit(2, 11), 11).is_none());
+    // Block 12 is outside the two block window starting at block 10
+    let batch = batcher.push(deposit(3, 12), 12).unwrap();
+    assert_eq!(batch.len(), 2);
+    let batch = batcher.take();
+    assert!(matches!(&batch[..], [TheaMessage::EvmDeposit(deposit)] if deposit.outgoing_nonce == 3));
+    assert!(batcher.deadline().is_none());
+}
+
+#[test]
+fn test_withdrawal_nonce() {
+    let message = thea_primitives::types::Message {
+        block_no: 10,
+        nonce: 42,
+        data: vec![1, 2],
+        network: 1,
+        payload_type: thea_primitives::types::PayloadType::L1Deposit,
+    };
+    let with_proof = TheaMessage::SubstrateMessageWithProof(message.encode(), 3, vec![]);
+    assert_eq!(WithdrawalRelay::nonce(&with_proof).unwrap(), Some(42));
+    assert_eq!(WithdrawalRelay::nonce(&TheaMessage::SubstrateMessage(message.encode())).unwrap(), Some(42));
+    let deposit = TheaMessage::EvmDeposit(EvmDeposit::new(vec![1; 32], 1, 100, 1, 10));
+    assert_eq!(WithdrawalRelay::nonce(&deposit).unwrap(), None);
+    assert!(WithdrawalRelay::nonce(&TheaMessage::SubstrateMessage(vec![1])).is_err());
+}
+
+#[test]
+fn test_pending_incoming() {
+    let checkpoint = temporary_checkpoint();
+    let pending = |nonce: u64| PendingIncoming { nonce, block_no: 10, data: vec![1, 2], submitted_at: Some(100) };
+    checkpoint.add_pending_incoming(1, &pending(257)).unwrap();
+    checkpoint.add_pending_incoming(1, &pending(2)).unwrap();
+    checkpoint.add_pending_incoming(2, &pending(1)).unwrap();
+    assert_eq!(checkpoint.pending_incoming(1).unwrap(), vec![pending(2), pending(257)]);
+    checkpoint.remove_pending_incoming(1, 2).unwrap();
+    assert_eq!(checkpoint.pending_incoming(1).unwrap(), vec![pending(257)]);
+    assert_eq!(checkpoint.pending_incoming(2).unwrap(), vec![pending(1)]);
+    assert!(!pending(1).is_expired(100 + MORTAL_PERIOD));
+    assert!(pending(1).is_expired(101 + MORTAL_PERIOD));
+    // Without an extrinsic carrying it, the message is as good as expired
+    assert!(PendingIncoming { submitted_at: None, ..pending(1) }.is_expired(0));
+}
+
+fn pending_incoming(nonce: u64, submitted_at: Option<u64>) -> PendingIncoming {
+    PendingIncoming { nonce, block_no: 10, data: vec![nonce as u8], submitted_at }
+}
+
+#[test]
+fn test_next_incoming_nonce() {
+    assert_eq!(IncomingNonceManager::next_nonce(3, &[]), 4);
+    let pending = vec![pending_incoming(4, Some(100)), pending_incoming(5, Some(100))];
+    assert_eq!(IncomingNonceManager::next_nonce(3, &pending), 6);
+    // The message at 5 was dead-lettered, its nonce is handed out again before anything past it
+    let pending = vec![pending_incoming(4, Some(100)), pending_incoming(6, Some(100))];
+    assert_eq!(IncomingNonceManager::next_nonce(3, &pending), 5);
+}
+
+#[test]
+fn test_incoming_resubmissions() {
+    let expired = 101 + MORTAL_PERIOD;
+    // Nothing to do while every extrinsic may still be included
+    let pending = vec![pending_incoming(4, Some(100)), pending_incoming(5, Some(expired))];
+    assert!(IncomingNonceManager::resubmissions(3, 100 + MORTAL_PERIOD, &pending).is_empty());
+    // Only the expired message is renumbered, the one still valid keeps its nonce and is not resubmitted
+    let pending = vec![pending_incoming(5, Some(100)), pending_incoming(6, Some(expired))];
+    assert_eq!(
+        IncomingNonceManager::resubmissions(3, expired, &pending),
+        vec![(4, pending_incoming(5, Some(100)))]
+    );
+    // Messages behind one that may still be included wait for it instead of failing again
+    let pending = vec![
+        pending_incoming(4, Some(100)),
+        pending_incoming(5, Some(expired)),
+        pending_incoming(6, None),
+    ];
+    assert_eq!(
+        IncomingNonceManager::resubmissions(3, expired, &pending),
+        vec![(4, pending_incoming(4, Some(100)))]
+    );
+    // Once it is finalized they follow it in order
+    let pending = vec![pending_incoming(6, None), pending_incoming(8, None)];
+    assert_eq!(
+        IncomingNonceManager::resubmissions(5, expired, &pending),
+        vec![(6, pending_incoming(6, None)), (7, pending_incoming(8, None))]
+    );
+}
+
+#[tokio::test]
+async fn test_incoming_dispatch_failures() {
+    let checkpoint = temporary_checkpoint();
+    let manager = IncomingNonceManager::new(1, checkpoint.clone());
+    let messages = vec![TheaMessage::EvmDeposit(EvmDeposit::new(vec![1; 32], 1, 100, 1, 10))];
+    let nonce_mismatch = RelayerError::TheaError(crate::substrateclient::polkadex::thea::Error::MessageNonce);
+    // Dispatched before the previous nonce landed, the message stays pending for reconciliation however often it happens
+    let submitted = pending_incoming(5, Some(100));
+    checkpoint.add_pending_incoming(1, &submitted).unwrap();
+    manager.record_failure(&submitted, &nonce_mismatch, &messages).await.unwrap();
+    assert_eq!(checkpoint.pending_incoming(1).unwrap(), vec![pending_incoming(5, None)]);
+    assert!(checkpoint.dead_letters().unwrap().is_empty());
+    // The failure of an extrinsic already replaced by reconciliation is ignored
+    let resubmitted = pending_incoming(5, Some(200));
+    checkpoint.add_pending_incoming(1, &resubmitted).unwrap();
+    manager.record_failure(&submitted, &RelayerError::ExtrinsicFailed("Other".to_string()), &messages).await.unwrap();
+    assert_eq!(checkpoint.pending_incoming(1).unwrap(), vec![resubmitted.clone()]);
+    assert!(checkpoint.dead_letters().unwrap().is_empty());
+    // Any other failure moves the deposits to dead letters
+    manager.record_failure(&resubmitted, &RelayerError::ExtrinsicFailed("Other".to_string()), &messages).await.unwrap();
+    assert!(checkpoint.pending_incoming(1).unwrap().is_empty());
+    let dead_letters = checkpoint.dead_letters().unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].attempts, 1);
+}
+
+#[test]
+fn test_replace_pending_incoming() {
+    let checkpoint = temporary_checkpoint();
+    let manager = IncomingNonceManager::new(1, checkpoint.clone());
+    let old = pending_incoming(6, None);
+    checkpoint.add_pending_incoming(1, &old).unwrap();
+    checkpoint.add_pending_incoming(1, &pending_incoming(8, None)).unwrap();
+    // Renumbered, the deposits move to the new nonce
+    let renumbered = PendingIncoming { nonce: 5, submitted_at: Some(200), ..old.clone() };
+    manager.replace_pending(&old, &renumbered).unwrap();
+    assert_eq!(checkpoint.pending_incoming(1).unwrap(), vec![renumbered.clone(), pending_incoming(8, None)]);
+    // Resubmitted with the same nonce, the record is rewritten in place
+    let resubmitted = PendingIncoming { submitted_at: Some(300), ..renumbered.clone() };
+    manager.replace_pending(&renumbered, &resubmitted).unwrap();
+    assert_eq!(checkpoint.pending_incoming(1).unwrap(), vec![resubmitted, pending_incoming(8, None)]);
+}
+
+#[test]
+fn test_unsubmitted_deposits() {
+    let deposit = |nonce: u64| SubstrateClient::evm_deposit(EvmDeposit::new(vec![1; 32], 1, 100, nonce, 10)).unwrap();
+    let pending = PendingIncoming { nonce: 7, block_no: 10, data: vec![deposit(1), deposit(2)].encode(), submitted_at: Some(100) };
+    // Replaying the same batch after a restart submits nothing
+    assert_eq!(IncomingNonceManager::unsubmitted_deposits(&[pending.clone()], pending.data.clone()).unwrap(), None);
+    // Regrouped with a new deposit only the new one is submitted
+    let regrouped = vec![deposit(2), deposit(3)].encode();
+    assert_eq!(
+        IncomingNonceManager::unsubmitted_deposits(&[pending.clone()], regrouped).unwrap(),
+        Some(vec![deposit(3)].encode())
+    );
+    let fresh = vec![deposit(4)].encode();
+    assert_eq!(IncomingNonceManager::unsubmitted_deposits(&[pending], fresh.clone()).unwrap(), Some(fresh));
+}
+
+#[test]
+fn test_decode_revert() {
+    let target = Address::repeat_byte(0x11);
+    let mut data = ethers::utils::id("AddressEmptyCode(address)").to_vec();
+    data.extend(ethers::abi::encode(&[ethers::abi::Token::Address(target)]));
+    let reason = decode_revert(&data);
+    assert!(reason.contains("AddressEmptyCode"), "{}", reason);
+    assert_eq!(decode_revert(&[0xde, 0xad]), "0xdead");
+}
+
+#[test]
+fn test_fee_strategy_caps() {
+    let strategy = FeeStrategy {
+        max_fee_per_gas: Some(U256::from(50)),
+        max_priority_fee_per_gas: Some(U256::from(80)),
+        ..Default::default()
+    };
+    assert_eq!(strategy.capped_fees(U256::from(40), U256::from(2)), (U256::from(40), U256::from(2)));
+    assert_eq!(strategy.capped_fees(U256::from(90), U256::from(2)), (U256::from(50), U256::from(2)));
+    // A priority fee above the max fee cap is clamped instead of raising the max fee past it
+    assert_eq!(strategy.capped_fees(U256::from(90), U256::from(70)), (U256::from(50), U256::from(50)));
+    // Without caps the max fee still covers the priority fee
+    assert_eq!(FeeStrategy::default().capped_fees(U256::from(5), U256::from(7)), (U256::from(7), U256::from(7)));
+}
+
+#[test]
+fn test_fee_strategy_bump() {
+    let strategy = FeeStrategy {
+        max_fee_per_gas: Some(U256::from(200)),
+        ..Default::default()
+    };
+    let mut tx = strategy.transaction(Address::zero(), Bytes::default());
+    let eip1559_tx = tx.as_eip1559_mut().unwrap();
+    eip1559_tx.max_fee_per_gas = Some(U256::from(100));
+    eip1559_tx.max_priority_fee_per_gas = Some(U256::from(10));
+    assert!(strategy.bump(&mut tx));
+    assert_eq!(tx.as_eip1559_ref().unwrap().max_fee_per_gas, Some(U256::from(125)));
+    assert_eq!(tx.as_eip1559_ref().unwrap().max_priority_fee_per_gas, Some(U256::from(12)));
+    assert!(strategy.bump(&mut tx));
+    assert!(strategy.bump(&mut tx));
+    // 195 * 1.25 would go over the cap of 200
+    assert!(!strategy.bump(&mut tx));
+    assert_eq!(tx.as_eip1559_ref().unwrap().max_fee_per_gas, Some(U256::from(195)));
+
+    let legacy = FeeStrategy { legacy: true, ..Default::default() };
+    let mut tx = legacy.transaction(Address::zero(), Bytes::default());
+    tx.set_gas_price(100);
+    assert!(legacy.bump(&mut tx));
+    assert_eq!(tx.gas_price(), Some(U256::from(125)));
+}
+
+#[test]
+fn test_verify_signatures() {
+    let validators: Vec<LocalWallet> = (0..4u8).map(|seed| LocalWallet::from_bytes(&[seed + 1; 32]).unwrap()).collect();
+    let message = b"thea message".to_vec();
+    let message_hash = H256(sp_core::hashing::keccak_256(&message));
+    let sign = |index: usize| {
+        let signature = validators[index].sign_hash(message_hash).unwrap();
+        let mut raw = [0u8; 65];
+        raw.copy_from_slice(&signature.to_vec());
+        // Substrate signatures carry the recovery id as 0 or 1
+        raw[64] -= 27;
+        (index as u32, Signature::from_raw(raw))
+    };
+    let signatures = vec![sign(0), sign(1), sign(2), sign(3)];
+    // Only the indexes returned by getValidatorIndex are checked, against the addresses the contract holds
+    let checked = vec![(1, validators[1].address()), (3, validators[3].address())];
+    assert!(verify_signatures(&message, &checked, 2, &signatures).is_ok());
+    assert!(matches!(
+        verify_signatures(&message, &checked, 3, &signatures),
+        Err(RelayerError::NotEnoughSignatures(2, 3))
+    ));
+    // Signature of validator 2 claimed for index 1
+    let (_, misplaced) = sign(2);
+    let tampered = vec![sign(0), (1, misplaced), sign(3)];
+    assert!(matches!(
+        verify_signatures(&message, &checked, 2, &tampered),
+        Err(RelayerError::NotEnoughSignatures(1, 2))
+    ));
+    // A zeroed placeholder validator never counts
+    let placeholder = vec![(1, validators[1].address()), (2, Address::zero())];
+    assert!(matches!(
+        verify_signatures(&message, &placeholder, 2, &signatures),
+        Err(RelayerError::NotEnoughSignatures(1, 2))
+    ));
+}
+
+#[test]
+fn test_check_validator_set() {
+    assert!(check_validator_set(3, 3).is_ok());
+    assert!(check_validator_set(2, 3).is_ok());
+    let err = check_validator_set(4, 3).unwrap_err();
+    assert!(matches!(err, RelayerError::UnknownValidatorSet(4, 3)));
+    // Waits for the rotation rather than dead-lettering right away
+    assert!(err.is_retryable());
+}
+
+#[test]
+fn test_pending_deposits() {
+    let deposit = |block_number: u64| {
+        TheaMessage::EvmDeposit(EvmDeposit::new(vec![1; 32], 1, 100, 0, block_number))
+    };
+    let (fork_a, fork_b) = (H256::from([1; 32]), H256::from([2; 32]));
+    let mut pending = PendingDeposits::default();
+    pending.push(fork_a, EvmCursor::new(12, 0), deposit(12));
+    pending.push(fork_a, EvmCursor::new(10, 3), deposit(10));
+    pending.push(fork_a, EvmCursor::new(11, 1), deposit(11));
+    assert_eq!(pending.first_block(), Some(10));
+    assert!(pending.contains(fork_a, 1));
+    // The log of block 11 was reorged out and re-included under another block hash
+    assert!(pending.remove(fork_a, 1));
+    assert!(!pending.remove(fork_a, 1));
+    pending.push(fork_b, EvmCursor::new(11, 0), deposit(11));
+    assert!(pending.contains(fork_b, 0) && !pending.contains(fork_b, 1));
+    let released = pending.release(11);
+    let cursors: Vec<EvmCursor> = released.iter().map(|(cursor, _)| *cursor).collect();
+    assert_eq!(cursors, vec![EvmCursor::new(10, 3), EvmCursor::new(11, 0)]);
+    assert!(pending.release(11).is_empty());
+    assert_eq!(pending.first_block(), Some(12));
+}
+
+#[test]
+fn test_claim_delay_required() {
+    use structopt::StructOpt;
+    let args = ["relayer", "thea_abi.json", "-n", "1", "-k", "2", "--claim-withdrawals"];
+    // Without the contract's delay the claimer would race the watchtower
+    assert!(crate::cli::Cli::from_iter_safe(args).is_err());
+    let cli = crate::cli::Cli::from_iter_safe(args.iter().chain(&["--withdrawal-claim-delay-secs", "86400"])).unwrap();
+    assert_eq!(cli.withdrawal_claim_delay_secs, Some(86400));
+}
+
+#[test]
+fn test_due_withdrawals() {
+    let checkpoint = temporary_checkpoint();
+    let claims = vec![
+        PendingClaim { nonce: 4, processed_at: 1_000, withdrawals: 3 },
+        PendingClaim { nonce: 5, processed_at: 1_100, withdrawals: 1 },
+    ];
+    let delay = Duration::from_secs(600);
+    let is_claimed = |nonce, index| checkpoint.is_withdrawal_claimed(nonce, index);
+    // Nothing is claimed before the contract's delay has passed
+    assert!(due_withdrawals(&claims, 1_599, delay, is_claimed).unwrap().is_empty());
+    assert_eq!(due_withdrawals(&claims, 1_600, delay, is_claimed).unwrap(), vec![(4, vec![0, 1, 2])]);
+    // Withdrawals claimed by anyone are not read or claimed again
+    checkpoint.add_claimed_withdrawal(4, 1).unwrap();
+    checkpoint.add_claimed_withdrawal(5, 0).unwrap();
+    assert_eq!(
+        due_withdrawals(&claims, 1_700, delay, is_claimed).unwrap(),
+        vec![(4, vec![0, 2]), (5, vec![])]
+    );
+}
+
+#[test]
+fn test_withdrawal_mismatch() {
+    let recipient = Address::from([7; 20]);
+    let pending = PendingWithdrawal { index: 0, asset_id: 1, amount: 500, recipient, is_blocked: false };
+    let expected = ExpectedWithdrawal { asset_id: 1, amount: 500, recipient: recipient.as_bytes().to_vec() };
+    assert_eq!(withdrawal_mismatch(&pending, Some(&expected)), None);
+    assert!(withdrawal_mismatch(&pending, None).is_some());
+    assert!(withdrawal_mismatch(&PendingWithdrawal { amount: 501, ..pending.clone() }, Some(&expected)).is_some());
+    assert!(withdrawal_mismatch(&PendingWithdrawal { asset_id: 2, ..pending.clone() }, Some(&expected)).is_some());
+    assert!(withdrawal_mismatch(&PendingWithdrawal { recipient: Address::from([8; 20]), ..pending.clone() }, Some(&expected)).is_some());
+    // A Substrate destination that is not an EVM address never matches
+    let malformed = ExpectedWithdrawal { recipient: vec![7; 32], ..expected };
+    assert!(withdrawal_mismatch(&pending, Some(&malformed)).is_some());
+}
+
+#[test]
+fn test_withdrawals_to_block() {
+    let recipient = Address::from([7; 20]);
+    let expected = vec![
+        ExpectedWithdrawal { asset_id: 1, amount: 500, recipient: recipient.as_bytes().to_vec() },
+        ExpectedWithdrawal { asset_id: 1, amount: 600, recipient: recipient.as_bytes().to_vec() },
+    ];
+    let pending = |index: u64, amount: u128| PendingWithdrawal { index, asset_id: 1, amount, recipient, is_blocked: false };
+    let checkpoint = temporary_checkpoint();
+    let is_claimed = |index| checkpoint.is_withdrawal_claimed(3, index);
+    // Matching withdrawals are left alone
+    assert!(withdrawals_to_block(vec![pending(0, 500), pending(1, 600)], &expected, is_claimed).unwrap().is_empty());
+    // A changed amount and a withdrawal Polkadex never signed are blocked
+    let to_block = withdrawals_to_block(vec![pending(0, 500), pending(1, 900), pending(2, 1)], &expected, is_claimed).unwrap();
+    assert_eq!(to_block.iter().map(|(pending, _)| pending.index).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(to_block[0].1, "amount 900 instead of 600");
+    // Already blocked or claimed ones cannot be blocked again
+    checkpoint.add_claimed_withdrawal(3, 2).unwrap();
+    let blocked = PendingWithdrawal { is_blocked: true, ..pending(1, 900) };
+    assert!(withdrawals_to_block(vec![blocked, pending(2, 1)], &expected, is_claimed).unwrap().is_empty());
+}
+
+#[test]
+fn test_outgoing_message_not_found_retryable() {
+    // Polkadex may not have stored the signed message yet when the stream sees the nonce
+    assert!(RelayerError::OutgoingMessageNotFound(3).is_retryable());
+}
@@ -14,50 +14,62 @@ pub trait Message {
     ) -> Self::ProcessedTheaMessage;
 }
 
-#[derive(Encode, Debug, Serialize, Deserialize)]
+#[derive(Clone, Encode, Debug, Serialize, Deserialize)]
 pub struct EvmDeposit {
     pub(crate) recipient: Vec<u8>,
     pub(crate) asset_id: u128,
     pub(crate) amount: u128,
-    pub(crate) outgoing_nonce: u64
+    pub(crate) outgoing_nonce: u64,
+    /// EVM block the deposit event was emitted in
+    #[serde(default)]
+    pub(crate) block_number: u64
 }
 
 impl EvmDeposit {
-    pub fn new(recipient: Vec<u8>, asset_id: u128, amount: u128, outgoing_nonce: u64) -> Self {
+    pub fn new(recipient: Vec<u8>, asset_id: u128, amount: u128, outgoing_nonce: u64, block_number: u64) -> Self {
         Self {
             recipient,
             asset_id,
             amount,
             outgoing_nonce,
+            block_number,
         }
     }
 }
 
-#[derive(Encode, Debug, Serialize, Deserialize)]
+#[derive(Clone, Encode, Debug, Serialize, Deserialize)]
 pub struct ObEvmDeposit {
+    /// Transaction hash and log index of the deposit event, the event carries no nonce
+    pub id: Vec<u8>,
     pub main_account: Vec<u8>,
     pub trading_account: Vec<u8>,
     pub asset_id: u128,
     pub amount: u128,
+    #[serde(default)]
+    pub block_number: u64,
 }
 
 impl ObEvmDeposit {
     pub fn new(
+        id: Vec<u8>,
         main_account: Vec<u8>,
         trading_account: Vec<u8>,
         asset_id: u128,
         amount: u128,
+        block_number: u64,
     ) -> Self {
         Self {
+            id,
             main_account,
             trading_account,
             asset_id,
             amount,
+            block_number,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum TheaMessage {
     EvmDeposit(EvmDeposit),
     ObEvmDeposit(ObEvmDeposit),
@@ -0,0 +1,175 @@
+use crate::checkpoint::{CheckpointStore, EvmCursor, EvmStream, EvmTxStatus, WatchtowerAlert};
+use crate::error::RelayerError;
+use crate::evmclient::{EvmClient, PendingWithdrawal};
+use crate::substrateclient::SubstrateClient;
+use ethers::types::{Address, H256};
+use ethers::utils::hex;
+use std::time::Duration;
+use thea_primitives::types::Withdraw;
+
+/// How often messages processed by the contract are cross-checked
+const WATCHTOWER_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Withdrawal as signed by the Thea validators on Polkadex
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExpectedWithdrawal {
+    pub asset_id: u128,
+    pub amount: u128,
+    pub recipient: Vec<u8>,
+}
+
+impl From<Withdraw> for ExpectedWithdrawal {
+    fn from(withdraw: Withdraw) -> Self {
+        Self {
+            asset_id: withdraw.asset_id,
+            amount: withdraw.amount,
+            recipient: withdraw.destination,
+        }
+    }
+}
+
+/// Reason the pending withdrawal differs from the signed one, `None` when they match
+pub fn withdrawal_mismatch(pending: &PendingWithdrawal, expected: Option<&ExpectedWithdrawal>) -> Option<String> {
+    let Some(expected) = expected else {
+        return Some("no matching withdrawal signed on Polkadex".to_string());
+    };
+    if pending.asset_id != expected.asset_id {
+        return Some(format!("asset {:?} instead of {:?}", pending.asset_id, expected.asset_id));
+    }
+    if pending.amount != expected.amount {
+        return Some(format!("amount {:?} instead of {:?}", pending.amount, expected.amount));
+    }
+    if expected.recipient.len() != Address::len_bytes() || pending.recipient != Address::from_slice(&expected.recipient) {
+        return Some(format!("recipient {:?} instead of 0x{}", pending.recipient, hex::encode(&expected.recipient)));
+    }
+    None
+}
+
+/// Pending withdrawals that differ from the signed ones along with the reason, leaving out the ones already
+/// blocked or claimed since blocking those can only revert
+pub fn withdrawals_to_block<F>(
+    pending: Vec<PendingWithdrawal>,
+    expected: &[ExpectedWithdrawal],
+    is_claimed: F,
+) -> Result<Vec<(PendingWithdrawal, String)>, RelayerError>
+where
+    F: Fn(u64) -> Result<bool, RelayerError>,
+{
+    let mut mismatched = vec![];
+    for pending in pending {
+        if pending.is_blocked || is_claimed(pending.index)? {
+            continue;
+        }
+        if let Some(reason) = withdrawal_mismatch(&pending, expected.get(pending.index as usize)) {
+            mismatched.push((pending, reason));
+        }
+    }
+    Ok(mismatched)
+}
+
+/// Blocks pending withdrawals on the EVM side whose asset, amount or recipient differ from what Polkadex signed
+#[derive(Clone, Debug)]
+pub struct Watchtower {
+    evm_client: EvmClient,
+    substrate_client: SubstrateClient,
+    checkpoint: CheckpointStore,
+}
+
+impl Watchtower {
+    pub fn new(evm_client: EvmClient, substrate_client: SubstrateClient, checkpoint: CheckpointStore) -> Self {
+        Self {
+            evm_client,
+            substrate_client,
+            checkpoint,
+        }
+    }
+
+    pub async fn run(&self) -> Result<(), RelayerError> {
+        println!("Watchtower checking withdrawals of processed messages");
+        let mut interval = tokio::time::interval(WATCHTOWER_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.scan().await?;
+        }
+    }
+
+    /// Checks every message processed since the last scanned block, resuming from a message whose check hit a node error
+    async fn scan(&self) -> Result<(), RelayerError> {
+        let head = self.evm_client.block_number().await?;
+        let from_block = match self.checkpoint.evm_cursor(EvmStream::Watchtower)? {
+            Some(cursor) => cursor.block_number.saturating_add(1),
+            None => self.evm_client.start_block().unwrap_or(head),
+        };
+        if from_block > head {
+            return Ok(());
+        }
+        // Claims up to the head are known before checking, a claimed withdrawal can no longer be blocked
+        for (nonce, index) in self.evm_client.claimed_withdrawals(from_block..=head).await? {
+            self.checkpoint.add_claimed_withdrawal(nonce, index)?;
+        }
+        for (nonce, block_number) in self.evm_client.processed_messages(from_block..=head).await? {
+            match self.check(nonce).await {
+                Ok(()) => {}
+                Err(err) if err.is_fatal() => return Err(err),
+                Err(err) if err.is_retryable() || matches!(err, RelayerError::EvmTransactionStuck(_)) => {
+                    println!("Failed to check withdrawals of message {:?}, retrying: {:?}", nonce, err);
+                    // Already checked messages of the block are skipped as blocked on the next pass
+                    return self
+                        .checkpoint
+                        .set_evm_cursor(EvmStream::Watchtower, EvmCursor::new(block_number.saturating_sub(1), 0));
+                }
+                // Retrying cannot change the outcome, so the message is left unchecked
+                Err(err) => println!("ALERT: skipping withdrawals of message {:?}: {:?}", nonce, err),
+            }
+        }
+        self.checkpoint.set_evm_cursor(EvmStream::Watchtower, EvmCursor::new(head, 0))
+    }
+
+    /// Blocks the pending withdrawals of the message that positively differ from the signed ones
+    async fn check(&self, nonce: u64) -> Result<(), RelayerError> {
+        // Pinned to the finalized block the relayer walked, not the latest one
+        let block_hash = self
+            .checkpoint
+            .last_finalized_block(self.substrate_client.network_id())?
+            .ok_or(RelayerError::OutgoingMessageNotFound(nonce))?;
+        let expected: Vec<ExpectedWithdrawal> = self
+            .substrate_client
+            .outgoing_withdrawals(nonce, H256(block_hash))
+            .await?
+            .into_iter()
+            .map(ExpectedWithdrawal::from)
+            .collect();
+        // One index past the signed ones catches a withdrawal Polkadex never signed
+        let pending = self
+            .evm_client
+            .pending_withdrawals(nonce, 0..expected.len() as u64 + 1)
+            .await?;
+        let mismatched = withdrawals_to_block(pending, &expected, |index| self.checkpoint.is_withdrawal_claimed(nonce, index))?;
+        for (pending, reason) in mismatched {
+            println!("ALERT: withdrawal {:?} of message {:?} does not match Polkadex: {}", pending.index, nonce, reason);
+            let result = self.evm_client.block_transaction(nonce, pending.index).await;
+            if let Err(err) = &result {
+                if err.is_fatal() || err.is_retryable() {
+                    return result.map(|_| ());
+                }
+            }
+            // Reverts are recorded and not retried, `blockTransaction` may be restricted or the withdrawal already claimed
+            self.checkpoint.add_watchtower_alert(&WatchtowerAlert {
+                nonce,
+                index: pending.index,
+                reason,
+                block_tx: result.as_ref().ok().cloned(),
+                error: result.as_ref().err().map(|err| err.to_string()),
+            })?;
+            let Ok(outcome) = result else {
+                continue;
+            };
+            self.checkpoint.add_evm_tx_outcome(&outcome)?;
+            match outcome.status {
+                EvmTxStatus::Confirmed(_) | EvmTxStatus::Reverted(_) => {}
+                EvmTxStatus::Stuck => return Err(RelayerError::EvmTransactionStuck(outcome.nonce)),
+            }
+        }
+        Ok(())
+    }
+}
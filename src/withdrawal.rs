@@ -0,0 +1,166 @@
+use crate::checkpoint::{CheckpointStore, EvmTxStatus};
+use crate::error::RelayerError;
+use crate::evmclient::EvmClient;
+use crate::proof::{check_validator_set, verify_signatures};
+use crate::traits::TheaMessage;
+use parity_scale_codec::Decode;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thea_primitives::types::Message;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::{watch, Mutex};
+
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Relays signed outgoing messages to the contract strictly in nonce order. The contract rejects every nonce
+/// after a missing one, so a message is retried until it is processed instead of being dead-lettered.
+#[derive(Clone, Debug)]
+pub struct WithdrawalRelay {
+    evm_client: EvmClient,
+    checkpoint: CheckpointStore,
+    // Shared so a restarted run keeps reading the same queue
+    receiver: Arc<Mutex<UnboundedReceiver<TheaMessage>>>,
+    known_validator_set: watch::Receiver<u64>,
+}
+
+impl WithdrawalRelay {
+    pub fn new(
+        evm_client: EvmClient,
+        checkpoint: CheckpointStore,
+        receiver: UnboundedReceiver<TheaMessage>,
+        known_validator_set: watch::Receiver<u64>,
+    ) -> Self {
+        Self {
+            evm_client,
+            checkpoint,
+            receiver: Arc::new(Mutex::new(receiver)),
+            known_validator_set,
+        }
+    }
+
+    /// Outgoing nonce of a message bound for the contract, `None` for deposits
+    pub fn nonce(message: &TheaMessage) -> Result<Option<u64>, RelayerError> {
+        match message {
+            TheaMessage::SubstrateMessage(message) | TheaMessage::SubstrateMessageWithProof(message, _, _) => {
+                Ok(Some(Message::decode(&mut &message[..])?.nonce))
+            }
+            TheaMessage::EvmDeposit(_) | TheaMessage::ObEvmDeposit(_) => Ok(None),
+        }
+    }
+
+    pub async fn run(&self) -> Result<(), RelayerError> {
+        let mut receiver = self.receiver.lock().await;
+        // Replayed in-flight messages arrive in any order, the lowest nonce always goes first
+        let mut queue: BTreeMap<u64, TheaMessage> = BTreeMap::new();
+        loop {
+            if queue.is_empty() {
+                let Some(message) = receiver.recv().await else {
+                    return Ok(());
+                };
+                self.enqueue(&mut queue, message)?;
+            }
+            while let Ok(message) = receiver.try_recv() {
+                self.enqueue(&mut queue, message)?;
+            }
+            let Some((nonce, message)) = queue.pop_first() else {
+                continue;
+            };
+            self.relay(nonce, &message).await?;
+            self.checkpoint.remove_in_flight(CheckpointStore::in_flight_key(&message)?)?;
+        }
+    }
+
+    fn enqueue(&self, queue: &mut BTreeMap<u64, TheaMessage>, message: TheaMessage) -> Result<(), RelayerError> {
+        match Self::nonce(&message) {
+            Ok(Some(nonce)) => {
+                queue.insert(nonce, message);
+            }
+            Ok(None) => println!("Ignoring deposit {:?} sent to the withdrawal relay", message),
+            // Cannot be relayed at all, the contract would not decode it either
+            Err(err) => {
+                println!("Moving undecodable message {:?} to dead letters: {:?}", message, err);
+                self.checkpoint.add_dead_letter(&message, &err, 0)?;
+                self.checkpoint.remove_in_flight(CheckpointStore::in_flight_key(&message)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Retries with capped backoff until the contract has processed the nonce, by this relayer or another one.
+    /// A message the contract will never accept is reported as fatal, it stays in flight for the next start.
+    async fn relay(&self, nonce: u64, message: &TheaMessage) -> Result<(), RelayerError> {
+        if let TheaMessage::SubstrateMessageWithProof(_, validator_set_id, _) = message {
+            self.wait_for_validator_set(nonce, *validator_set_id).await;
+        }
+        let mut backoff = INITIAL_RETRY_BACKOFF;
+        loop {
+            let result = match self.evm_client.get_incoming_nonce().await {
+                Ok(incoming_nonce) if incoming_nonce >= nonce => {
+                    println!("Outgoing message {:?} already processed by the contract", nonce);
+                    return Ok(());
+                }
+                Ok(_) => self.submit(message.clone()).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) if err.is_fatal() => return Err(err),
+                // A stuck transaction only ran out of fee bumps, retrying replaces it again
+                Err(err) if !err.is_retryable() && !matches!(err, RelayerError::EvmTransactionStuck(_)) => {
+                    println!("ALERT: outgoing message {:?} is rejected, later withdrawals wait behind it: {:?}", nonce, err);
+                    self.checkpoint.add_dead_letter(message, &err, 1)?;
+                    return Err(RelayerError::WithdrawalRejected(nonce, err.to_string()));
+                }
+                Err(err) => {
+                    println!("Failed to relay outgoing message {:?}: {:?}, retrying in {:?}", nonce, err, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Holds the message until the rotation to the set that signed it has been relayed to the contract
+    async fn wait_for_validator_set(&self, nonce: u64, validator_set_id: u64) {
+        let mut known_validator_set = self.known_validator_set.clone();
+        let known_validator_set_id = *known_validator_set.borrow();
+        if validator_set_id <= known_validator_set_id {
+            return;
+        }
+        println!(
+            "Holding outgoing message {:?} signed by validator set {:?}, contract knows up to {:?}",
+            nonce, validator_set_id, known_validator_set_id
+        );
+        // The tracker never stops publishing while the relayer runs
+        let _ = known_validator_set.wait_for(|known| validator_set_id <= *known).await;
+    }
+
+    async fn submit(&self, message: TheaMessage) -> Result<(), RelayerError> {
+        let outcome = match message {
+            TheaMessage::SubstrateMessage(message) => self.evm_client.handle_substrate_message(message).await?,
+            TheaMessage::SubstrateMessageWithProof(message, validator_set_id, signature) => {
+                check_validator_set(validator_set_id, self.evm_client.known_validator_set_id().await?)?;
+                let indexes = signature.iter().map(|(index, _)| u64::from(*index)).collect();
+                let (validators, index_size) = self
+                    .evm_client
+                    .verification_validators(message.clone(), validator_set_id, indexes)
+                    .await?;
+                verify_signatures(&message, &validators, index_size, &signature)?;
+                // Submitted with exactly the signatures that were verified
+                let verified_indexes: Vec<u64> = validators.iter().map(|(index, _)| *index).collect();
+                self.evm_client
+                    .handle_substrate_message_with_proof(message, &verified_indexes, signature)
+                    .await?
+            }
+            TheaMessage::EvmDeposit(_) | TheaMessage::ObEvmDeposit(_) => return Ok(()),
+        };
+        self.checkpoint.add_evm_tx_outcome(&outcome)?;
+        match outcome.status {
+            EvmTxStatus::Confirmed(_) => Ok(()),
+            EvmTxStatus::Reverted(reason) => Err(RelayerError::EvmTransactionReverted(reason)),
+            EvmTxStatus::Stuck => Err(RelayerError::EvmTransactionStuck(outcome.nonce)),
+        }
+    }
+}